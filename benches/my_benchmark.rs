@@ -1,27 +1,101 @@
-//use lz4_compression::prelude::{decompress, compress};
-//use lz4_compression::decompress::decompress3;
-use lz4_compression::decompress::decompress;
-use lz4_compression::compress::compress;
-use lz4_compression::decompress_file;
+use lz_fear::framed::{CompressionSettings, Decompressor, LZ4FrameReader, decompress_frame, decompress_frame_slice};
+#[cfg(feature = "parallel")]
+use lz_fear::framed::parallel_decompress;
+use std::io::Read;
 use rand::prelude::*;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
 
-use std::io::Cursor;
+fn compress(data: &[u8], settings: &CompressionSettings) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    settings.compress(data, &mut compressed).unwrap();
+    compressed
+}
 
 fn criterion_benchmark(c: &mut Criterion) {
     let mut data = vec![0u8; 10_000_000];
     thread_rng().fill(&mut data[2_000_000..6_000_000]); // mixed
+    for (i, x) in data[6_000_000..].iter_mut().enumerate() {
+        *x = (i as u8).wrapping_mul(0xA) ^ (i >> 12) as u8; // compressible
+    }
+
+    let default_settings = CompressionSettings::default();
+    let mut small_blocks = CompressionSettings::default();
+    small_blocks.block_size(64 * 1024);
+    let compressed = compress(&data, &default_settings);
+    let small_blocks_compressed = compress(&data, &small_blocks);
+    let checksummed = compress(&data, small_blocks.block_checksums(true));
+    let mut stored_settings = CompressionSettings::default();
+    stored_settings.content_checksum(false);
+    let stored = compress(&data[2_000_000..6_000_000], &stored_settings);
+    let stored_small_blocks = compress(&data[2_000_000..6_000_000], stored_settings.block_size(64 * 1024));
+
+    let mut group = c.benchmark_group("framed");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("compress", |b| b.iter(|| compress(black_box(&data), &default_settings)));
+    group.bench_function("compress on 4 threads", |b| b.iter(|| {
+        let mut compressed = Vec::new();
+        default_settings.compress_parallel_blocks(black_box(data.as_slice()), &mut compressed, 4).unwrap();
+        compressed
+    }));
+    group.bench_function("decompress", |b| b.iter(|| decompress_frame(black_box(compressed.as_slice())).unwrap()));
+    group.bench_function("decompress 64K blocks", |b| b.iter(|| decompress_frame(black_box(small_blocks_compressed.as_slice())).unwrap()));
+    group.bench_function("decompress 64K blocks from slice", |b| b.iter(|| decompress_frame_slice(black_box(&small_blocks_compressed)).unwrap()));
+    #[cfg(feature = "parallel")]
+    group.bench_function("decompress 64K blocks on 4 threads", |b| b.iter(|| {
+        let mut output = std::io::Cursor::new(Vec::with_capacity(data.len()));
+        parallel_decompress(std::io::Cursor::new(black_box(small_blocks_compressed.as_slice())), &mut output, 4).unwrap();
+        output
+    }));
+    group.bench_function("read", |b| b.iter(|| {
+        let mut output = Vec::with_capacity(data.len());
+        LZ4FrameReader::new(black_box(compressed.as_slice())).unwrap().into_read().read_to_end(&mut output).unwrap();
+        output
+    }));
+    group.bench_function("read without content checksum", |b| b.iter(|| {
+        let mut output = Vec::with_capacity(data.len());
+        let mut reader = LZ4FrameReader::new(black_box(compressed.as_slice())).unwrap();
+        reader.skip_content_checksum(true);
+        reader.into_read().read_to_end(&mut output).unwrap();
+        output
+    }));
+    group.bench_function("decompress 64K blocks with block checksums", |b| b.iter(|| decompress_frame(black_box(checksummed.as_slice())).unwrap()));
+    group.finish();
 
-    let uncompressed_data: &[u8] = data.as_slice();
-    let compressed_data = compress(uncompressed_data);
+    let mut group = c.benchmark_group("framed stored");
+    group.throughput(Throughput::Bytes(4_000_000));
+    group.bench_function("compress", |b| b.iter(|| compress(black_box(&data[2_000_000..6_000_000]), &stored_settings)));
+    group.bench_function("decompress", |b| b.iter(|| decompress_frame(black_box(stored.as_slice())).unwrap()));
+    group.bench_function("decompress 64K blocks", |b| b.iter(|| decompress_frame(black_box(stored_small_blocks.as_slice())).unwrap()));
+    group.finish();
 
-    let cargo = include_bytes!("../sh.lz4") as &[u8];
-    c.bench_function("lz4 -d cargo", |b| b.iter(|| decompress_file(Cursor::new(cargo))));
+    // a short pattern repeated over and over compresses to long matches that overlap themselves
+    let pattern: Vec<u8> = (0..10_000_000).map(|i| b"overlapping matches, period 37 bytes. "[i % 37]).collect();
+    let pattern_compressed = compress(&pattern, &default_settings);
+    let mut group = c.benchmark_group("overlapping matches");
+    group.throughput(Throughput::Bytes(pattern.len() as u64));
+    group.bench_function("decompress", |b| b.iter(|| decompress_frame(black_box(pattern_compressed.as_slice())).unwrap()));
+    group.finish();
 
-    let mut group = c.benchmark_group("decompress");
-    group.bench_with_input("ours", &compressed_data.as_slice(), |b, c| b.iter(|| decompress(c)));
-    //group.bench_with_input("theirs", &compressed_data.as_slice(), |b, c| b.iter(|| decompress3(c)));
-    
+    let tiny_frames: Vec<Vec<u8>> = (0..100_000).map(|i| {
+        compress(format!("tiny frame number {}, tiny frame number {}", i, i).as_bytes(), &default_settings)
+    }).collect();
+    let mut group = c.benchmark_group("100k tiny frames");
+    group.sample_size(10);
+    group.bench_function("decompress_frame", |b| b.iter(|| {
+        for frame in &tiny_frames {
+            black_box(decompress_frame(frame.as_slice()).unwrap());
+        }
+    }));
+    group.bench_function("Decompressor", |b| b.iter(|| {
+        let mut decompressor = Decompressor::new();
+        let mut output = Vec::new();
+        for frame in &tiny_frames {
+            output.clear();
+            decompressor.decompress_into(frame.as_slice(), &mut output).unwrap();
+            black_box(&output);
+        }
+    }));
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
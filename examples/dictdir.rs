@@ -0,0 +1,26 @@
+use lz_fear::framed::{DictionaryProvider, LZ4FrameReader};
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::env;
+use fehler::throws;
+
+/// Dictionaries stored as `<id>.dict` in a directory.
+struct DictionaryDir(PathBuf);
+impl DictionaryProvider for DictionaryDir {
+    fn get(&self, id: u32) -> Option<Cow<'_, [u8]>> {
+        fs::read(self.0.join(format!("{}.dict", id))).ok().map(Cow::Owned)
+    }
+}
+
+/// Usage: dictdir <dictionary directory> <input> <output>
+#[throws(io::Error)]
+fn main() {
+    let dictionaries = DictionaryDir(env::args().nth(1).unwrap().into());
+    let file_in = File::open(env::args().nth(2).unwrap())?;
+    let mut file_out = BufWriter::new(File::create(env::args().nth(3).unwrap())?);
+
+    let mut lz4_reader = LZ4FrameReader::with_dictionary_provider(file_in, &dictionaries)?.into_read();
+    io::copy(&mut lz4_reader, &mut file_out)?;
+}
@@ -12,7 +12,7 @@ fn main() {
     
     CompressionSettings::default()
         .content_checksum(true)
-        .independent_blocks(true)
+        .unlinked_blocks()
         /*.block_size(64 * 1024).dictionary(0, &vec![0u8; 64 * 1024]).dictionary_id_nonsense_override(Some(42))*/
         .compress_with_size(file_in, file_out)?;
 }
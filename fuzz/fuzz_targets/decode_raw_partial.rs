@@ -0,0 +1,23 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use lz_fear::raw::{decompress_raw, decompress_raw_partial};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let max_out = usize::from(u16::from_le_bytes([data[0], data[1]]));
+    let block = &data[2..];
+
+    let mut partial = Vec::new();
+    let result = decompress_raw_partial(block, &[], &mut partial, max_out);
+    assert!(partial.len() <= max_out);
+
+    // whenever the whole block is valid, the partial output is the start of it
+    let mut full = Vec::new();
+    if decompress_raw(block, &[], &mut full, usize::MAX).is_ok() {
+        let len = result.expect("valid block failed to decompress partially");
+        assert_eq!(len, std::cmp::min(max_out, full.len()));
+        assert_eq!(partial, &full[..len]);
+    }
+});
@@ -9,7 +9,7 @@ fuzz_target!(|data: &[u8]| {
 
     CompressionSettings::default()
         .content_checksum(true)
-        .independent_blocks(true)
+        .unlinked_blocks()
         .compress(input, &mut output) //_with_size(input, &mut output)
         .expect("Could not compress input data");
 
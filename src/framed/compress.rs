@@ -2,13 +2,26 @@ use byteorder::{LE, WriteBytesExt};
 use std::hash::Hasher;
 use std::io::{self, Read, Write, Seek, SeekFrom, ErrorKind};
 use std::mem;
+use std::fmt;
+use std::cmp;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use twox_hash::XxHash32;
+#[cfg(feature = "xxhash64")]
+use twox_hash::XxHash64;
 use thiserror::Error;
-use fehler::{throws};
+use fehler::{throw, throws};
 
 use super::{MAGIC, INCOMPRESSIBLE, WINDOW_SIZE};
+#[cfg(feature = "xxhash64")]
+use super::{XXH64_FRAME_MAGIC, XXH64_FRAME_TAG};
 use super::header::{Flags, BlockDescriptor};
-use crate::raw::{U32Table, compress2, EncoderTable};
+use super::decompress::DecoderDictionary;
+use crate::raw::{U32Table, compress2_separate_window, compress_separate_window_into, compress_bound, EncoderTable};
 
 
 /// Errors when compressing an LZ4 frame.
@@ -20,11 +33,64 @@ pub enum CompressionError {
     WriteError(#[from] io::Error),
     #[error("the block size you asked for is not supported")]
     InvalidBlockSize,
+    #[error("the compressed frame would exceed the output limit")]
+    OutputLimitExceeded,
 }
 type Error = CompressionError; // do it this way for better docs
 impl From<Error> for io::Error {
     fn from(e: Error) -> io::Error {
-        io::Error::new(ErrorKind::Other, e)
+        // keep the kind of I/O errors so callers can still tell e.g. `WriteZero` apart
+        let kind = match &e {
+            Error::ReadError(e) | Error::WriteError(e) => e.kind(),
+            _ => ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Errors when parsing a parameter string (see `CompressionSettings::from_params_str`).
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ParamsParseError {
+    #[error("parameter strings must start with \"LZ4f\"")]
+    MissingPrefix,
+    #[error("unknown parameter: {0}")]
+    UnknownParameter(String),
+    #[error("invalid block size: {0}")]
+    InvalidBlockSize(String),
+    #[error("invalid dictionary id: {0}")]
+    InvalidDictionaryId(String),
+}
+
+/// The block sizes the frame format supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlockSize {
+    Max64KB,
+    Max256KB,
+    Max1MB,
+    Max4MB,
+}
+impl BlockSize {
+    /// A block size that suits `input_len` bytes of input.
+    ///
+    /// Small inputs get small blocks so we don't allocate 4 MiB buffers for a few kilobytes,
+    /// large inputs get large blocks for the better compression ratio.
+    pub fn recommended_for(input_len: usize) -> BlockSize {
+        match input_len {
+            x if x < 256 * 1024 => BlockSize::Max64KB,
+            x if x < 1024 * 1024 => BlockSize::Max256KB,
+            x if x < 4 * 1024 * 1024 => BlockSize::Max1MB,
+            _ => BlockSize::Max4MB,
+        }
+    }
+
+    /// The size in bytes, as `CompressionSettings::block_size` takes it.
+    pub fn bytes(self) -> usize {
+        match self {
+            BlockSize::Max64KB => 64 * 1024,
+            BlockSize::Max256KB => 256 * 1024,
+            BlockSize::Max1MB => 1024 * 1024,
+            BlockSize::Max4MB => 4 * 1024 * 1024,
+        }
     }
 }
 
@@ -33,6 +99,7 @@ impl From<Error> for io::Error {
 /// (An LZ4 file usually consists of a single frame.)
 ///
 /// Create it using `Default::default()`.
+#[derive(Clone)]
 pub struct CompressionSettings<'a> {
     independent_blocks: bool,
     block_checksums: bool,
@@ -40,6 +107,28 @@ pub struct CompressionSettings<'a> {
     block_size: usize,
     dictionary: Option<&'a [u8]>,
     dictionary_id: Option<u32>,
+    shrink_block_size: bool,
+    max_output_bytes: Option<u64>,
+    #[cfg(feature = "xxhash64")]
+    xxhash64_checksum: bool,
+    #[cfg(test)]
+    checksum_hasher: fn() -> ChecksumHasher,
+    incompressible_hook: Option<(f32, IncompressibleHook<'a>)>,
+}
+
+/// See `CompressionSettings::on_incompressible_ratio`.
+type IncompressibleHook<'a> = Arc<dyn Fn(f32) + Send + Sync + 'a>;
+
+/// The hasher for block and content checksums: xxhash32, as the spec demands.
+/// Tests can replace it (see `with_hasher`), so there it has to be a trait object.
+#[cfg(not(test))]
+type ChecksumHasher = XxHash32;
+#[cfg(test)]
+type ChecksumHasher = Box<dyn Hasher>;
+
+#[cfg(test)]
+fn xxhash32() -> ChecksumHasher {
+    Box::new(XxHash32::with_seed(0))
 }
 impl<'a> Default for CompressionSettings<'a> {
     fn default() -> Self {
@@ -50,21 +139,101 @@ impl<'a> Default for CompressionSettings<'a> {
             block_size: 4 * 1024 * 1024,
             dictionary: None,
             dictionary_id: None,
+            shrink_block_size: false,
+            max_output_bytes: None,
+            #[cfg(feature = "xxhash64")]
+            xxhash64_checksum: false,
+            #[cfg(test)]
+            checksum_hasher: xxhash32,
+            incompressible_hook: None,
         }
     }
 }
+/// Settings are equal if they produce the same frames: the dictionaries are compared by content.
+/// Hooks are only equal if they are the same closure (i.e. cloned from the same settings).
+/// The checksum hasher that tests can replace is ignored.
+impl<'a> PartialEq for CompressionSettings<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        let same_hook = match (&self.incompressible_hook, &other.incompressible_hook) {
+            (None, None) => true,
+            (Some((a, f)), Some((b, g))) => a.to_bits() == b.to_bits() && Arc::ptr_eq(f, g),
+            _ => false,
+        };
+        #[cfg(feature = "xxhash64")]
+        let same_xxhash64 = self.xxhash64_checksum == other.xxhash64_checksum;
+        #[cfg(not(feature = "xxhash64"))]
+        let same_xxhash64 = true;
+
+        self.independent_blocks == other.independent_blocks
+            && self.block_checksums == other.block_checksums
+            && self.content_checksum == other.content_checksum
+            && self.block_size == other.block_size
+            && self.dictionary == other.dictionary
+            && self.dictionary_id == other.dictionary_id
+            && self.shrink_block_size == other.shrink_block_size
+            && self.max_output_bytes == other.max_output_bytes
+            && same_xxhash64
+            && same_hook
+    }
+}
+impl<'a> Eq for CompressionSettings<'a> {}
+impl<'a> fmt::Debug for CompressionSettings<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("CompressionSettings");
+        s.field("independent_blocks", &self.independent_blocks)
+            .field("block_checksums", &self.block_checksums)
+            .field("content_checksum", &self.content_checksum)
+            .field("block_size", &self.block_size)
+            .field("dictionary_len", &self.dictionary.map(<[u8]>::len))
+            .field("dictionary_id", &self.dictionary_id)
+            .field("shrink_block_size", &self.shrink_block_size)
+            .field("max_output_bytes", &self.max_output_bytes);
+        #[cfg(feature = "xxhash64")]
+        s.field("xxhash64_checksum", &self.xxhash64_checksum);
+        s.field("incompressible_threshold", &self.incompressible_hook.as_ref().map(|(threshold, _)| threshold))
+            .finish_non_exhaustive()
+    }
+}
 impl<'a> CompressionSettings<'a> {
+    /// Settings that reproduce the output of the reference `lz4` command line tool (with default flags) byte for byte.
+    ///
+    /// That means independent blocks, a content checksum, no block checksums and 4 MiB blocks.
+    /// Just like the CLI, `compress` and friends shrink the block size to the smallest one that fits
+    /// if the entire input is smaller than a single block (the block size you set becomes an upper limit).
+    ///
+    /// If you add a dictionary, keep in mind that the CLI never writes a dictionary id
+    /// (see `dictionary_id_nonsense_override`).
+    pub fn lz4_cli_compatible() -> Self {
+        // apart from shrinking the block size, our defaults are the CLI's
+        Self { shrink_block_size: true, ..Self::default() }
+    }
+
     /// In independent mode, blocks are not allowed to reference data from previous blocks.
     /// Hence, using dependent blocks yields slightly better compression.
     /// The downside of dependent blocks is that seeking becomes impossible - the entire frame always has
     /// to be decompressed from the beginning.
     ///
     /// Blocks are independent by default.
+    #[deprecated(note = "`independent_blocks(false)` is easy to misread, use `linked_blocks` or `unlinked_blocks` instead")]
     pub fn independent_blocks(&mut self, v: bool) -> &mut Self {
         self.independent_blocks = v;
         self
     }
 
+    /// Allow blocks to reference data from previous blocks (see `independent_blocks`).
+    ///
+    /// This yields slightly better compression, but the frame can only be decompressed from the beginning.
+    pub fn linked_blocks(&mut self) -> &mut Self {
+        self.independent_blocks = false;
+        self
+    }
+
+    /// Don't allow blocks to reference data from previous blocks (see `independent_blocks`). This is the default.
+    pub fn unlinked_blocks(&mut self) -> &mut Self {
+        self.independent_blocks = true;
+        self
+    }
+
     /// Block checksums can help detect data corruption in storage and transit.
     /// They do not offer error correction though.
     ///
@@ -78,6 +247,7 @@ impl<'a> CompressionSettings<'a> {
     }
 
     /// The content checksum (also called frame checksum) is calculated over the contents of the entire frame.
+    /// It never spans more than one frame: every frame gets a fresh checksum, even if you reuse the settings.
     /// This makes them cheaper than block checksums as their size overhead is constant
     /// as well as marginally more useful, because they can help protect against incorrect decompression.
     ///
@@ -85,20 +255,65 @@ impl<'a> CompressionSettings<'a> {
     /// (and returned!), which is the downside of content checksums.
     ///
     /// Frame checksums are enabled by default.
+    /// Disabling both checksums on a frame with linked blocks logs a warning (via the `log` crate)
+    /// because corruption could then go entirely unnoticed.
     pub fn content_checksum(&mut self, v: bool) -> &mut Self {
         self.content_checksum = v;
         self
     }
 
+    /// In addition to the regular content checksum (xxhash32), append an xxhash64 of the content
+    /// in a skippable frame right after the LZ4 frame. The frame itself is unchanged, so every LZ4
+    /// implementation can still read it; they just skip the extra frame. Use
+    /// `decompress_frame_xxh64` to verify it.
+    ///
+    /// This is a lot stronger than xxhash32, but also specific to this library.
+    ///
+    /// Disabled by default. Requires the `xxhash64` feature.
+    #[cfg(feature = "xxhash64")]
+    pub fn xxhash64_checksum(&mut self, v: bool) -> &mut Self {
+        self.xxhash64_checksum = v;
+        self
+    }
+
+    /// Replace the hasher for block and content checksums, so tests can use one with a predictable result.
+    ///
+    /// The header checksum always uses xxhash32, otherwise nothing could read the frame at all.
+    #[cfg(test)]
+    pub(crate) fn with_hasher(&mut self, v: fn() -> ChecksumHasher) -> &mut Self {
+        self.checksum_hasher = v;
+        self
+    }
+
+    /// A fresh hasher for a block or content checksum.
+    #[cfg(not(test))]
+    fn checksum_hasher(&self) -> ChecksumHasher {
+        XxHash32::with_seed(0)
+    }
+
+    #[cfg(test)]
+    fn checksum_hasher(&self) -> ChecksumHasher {
+        (self.checksum_hasher)()
+    }
+
     /// Only valid values are 4MiB, 1MiB, 256KiB, 64KiB
     /// (TODO: better interface for this)
     ///
+    /// Whatever the frame format may allow in the future, blocks can never reach 2 GiB:
+    /// the top bit of the block length marks incompressible blocks.
+    ///
     /// The default block size is 4 MiB.
     pub fn block_size(&mut self, v: usize) -> &mut Self {
         self.block_size = v;
         self
     }
 
+    /// Use the block size that `BlockSize::recommended_for` picks for `input_len` bytes of input.
+    pub fn auto_block_size(&mut self, input_len: usize) -> &mut Self {
+        self.block_size = BlockSize::recommended_for(input_len).bytes();
+        self
+    }
+
     /// A dictionary is essentially a constant slice of bytes shared by the compressing and decompressing party.
     /// Using a dictionary can improve compression ratios, because the compressor can reference data from the dictionary.
     ///
@@ -116,6 +331,13 @@ impl<'a> CompressionSettings<'a> {
         self
     }
 
+    /// Like `dictionary`, but uses the xxhash32 of `dict` as its id, so you don't have to come up with one.
+    pub fn dictionary_auto_id(&mut self, dict: &'a [u8]) -> &mut Self {
+        let mut hasher = XxHash32::with_seed(0);
+        hasher.write(dict);
+        self.dictionary(hasher.finish() as u32, dict)
+    }
+
     /// The dictionary id header field is quite obviously intended to tell anyone trying to decompress your frame which dictionary to use.
     /// So it is only natural to assume that the *absence* of a dictionary id indicates that no dictionary was used.
     ///
@@ -132,55 +354,417 @@ impl<'a> CompressionSettings<'a> {
         self
     }
 
+    /// The dictionary and its id for decompressing what these settings compress.
+    ///
+    /// Returns `None` if there is no dictionary (or no id, see `dictionary_id_nonsense_override`).
+    pub fn decoder_dictionary(&self) -> Option<DecoderDictionary> {
+        Some(DecoderDictionary::new(self.dictionary_id?, self.dictionary?))
+    }
+
+    /// Give up with `OutputLimitExceeded` as soon as the frame would exceed this many bytes.
+    ///
+    /// This is meant for fixed-size storage: it saves you from compressing everything just to find out it doesn't fit.
+    /// When this happens, the writer has received an incomplete frame of at most `limit` bytes, which you should discard.
+    /// Only `compress` and friends honor this, `LZ4FrameWriter` doesn't.
+    ///
+    /// By default, there is no limit.
+    pub fn max_output_bytes(&mut self, limit: u64) -> &mut Self {
+        self.max_output_bytes = Some(limit);
+        self
+    }
+
+    /// Call `f` at the end of each frame in which the fraction of blocks that had to be stored
+    /// (because they were incompressible) exceeds `threshold`, with that fraction.
+    ///
+    /// This is a cheap signal that the data isn't worth compressing. Frames without blocks never trigger it.
+    pub fn on_incompressible_ratio(&mut self, threshold: f32, f: impl Fn(f32) + Send + Sync + 'a) -> &mut Self {
+        self.incompressible_hook = Some((threshold, Arc::new(f)));
+        self
+    }
+
+    /// Describe the framing parameters as a human-readable string, e.g. `LZ4f,block=4M,indep,csum`.
+    ///
+    /// This format is an extension specific to this library (nothing in the LZ4 spec), meant for config files
+    /// and command line tools. It consists of the prefix `LZ4f` followed by comma-separated parameters:
+    /// `block=<size>` (`64K`, `256K`, `1M`, `4M` or a number of bytes), `indep` (independent blocks),
+    /// `csum` (content checksum), `bcsum` (block checksums) and `dict-id=<id>`.
+    /// Anything that is not listed is disabled. Note that only the dictionary id is included,
+    /// the dictionary itself has to be provided separately.
+    pub fn to_params_str(&self) -> String {
+        let block_size = match self.block_size {
+            x if x == 4 * 1024 * 1024 => "4M".to_owned(),
+            x if x == 1024 * 1024 => "1M".to_owned(),
+            x if x == 256 * 1024 => "256K".to_owned(),
+            x if x == 64 * 1024 => "64K".to_owned(),
+            x => x.to_string(),
+        };
+        let mut params = format!("LZ4f,block={}", block_size);
+        if self.independent_blocks {
+            params.push_str(",indep");
+        }
+        if self.content_checksum {
+            params.push_str(",csum");
+        }
+        if self.block_checksums {
+            params.push_str(",bcsum");
+        }
+        if let Some(id) = self.dictionary_id {
+            params.push_str(&format!(",dict-id={}", id));
+        }
+        params
+    }
+
+    /// Parse a string produced by `to_params_str`.
+    ///
+    /// If there is no `block=` parameter, the default block size is used.
+    pub fn from_params_str(s: &str) -> Result<Self, ParamsParseError> {
+        let mut params = s.split(',');
+        if params.next() != Some("LZ4f") {
+            return Err(ParamsParseError::MissingPrefix);
+        }
+
+        let mut settings = CompressionSettings::default();
+        settings.linked_blocks().content_checksum(false);
+        for param in params {
+            match param {
+                "indep" => settings.independent_blocks = true,
+                "csum" => settings.content_checksum = true,
+                "bcsum" => settings.block_checksums = true,
+                _ if param.starts_with("block=") => {
+                    let value = &param["block=".len()..];
+                    let size = match value {
+                        "4M" => Some(4 * 1024 * 1024),
+                        "1M" => Some(1024 * 1024),
+                        "256K" => Some(256 * 1024),
+                        "64K" => Some(64 * 1024),
+                        _ => value.parse().ok(),
+                    };
+                    settings.block_size = size.filter(|&size| BlockDescriptor::new(size).is_some())
+                        .ok_or_else(|| ParamsParseError::InvalidBlockSize(value.to_owned()))?;
+                }
+                _ if param.starts_with("dict-id=") => {
+                    let value = &param["dict-id=".len()..];
+                    let id = value.parse().map_err(|_| ParamsParseError::InvalidDictionaryId(value.to_owned()))?;
+                    settings.dictionary_id = Some(id);
+                }
+                _ => return Err(ParamsParseError::UnknownParameter(param.to_owned())),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Start a push-based compression: returns an `LZ4FrameWriter` that compresses everything you write into it.
+    ///
+    /// The frame header is written immediately, so any I/O errors there show up right away.
+    /// Don't forget to call `finish` on the writer once you're done.
+    #[throws]
+    pub fn streaming_compress<W: Write>(&self, writer: W) -> LZ4FrameWriter<'a, W> {
+        LZ4FrameWriter::new(writer, self)?
+    }
+
+    /// Compress `input` into a single raw LZ4 block, without any framing.
+    ///
+    /// Returns whether the block is actually compressed along with the block bytes.
+    /// If it is not (because the data is incompressible), the bytes are just a copy of `input`
+    /// and you have to mark the block as stored in your own container - in an LZ4 frame,
+    /// this is what the `INCOMPRESSIBLE` bit of the block length does.
+    ///
+    /// The only setting this honors is the dictionary. Since there is no frame, the block size
+    /// is not enforced either (but keep it in mind if the block ever ends up in a frame).
+    pub fn compress_single_block(&self, input: &[u8]) -> (bool, Vec<u8>) {
+        let (in_buffer, cursor, mut table) = self.single_block_input(input);
+        let mut out_buffer = vec![0u8; input.len()];
+        match compress_or_store(&in_buffer, cursor, &mut table, &mut out_buffer) {
+            Some(written_len) => {
+                out_buffer.truncate(written_len);
+                (true, out_buffer)
+            }
+            None => (false, input.to_vec()),
+        }
+    }
+
+    /// Compress `input` into a single raw LZ4 block and write it to `writer` at `offset`, returning the length of the block.
+    ///
+    /// This is for updating compressed data in place, e.g. the pages of a database file.
+    /// Unlike `compress_single_block`, the block is always compressed (even if that makes it slightly larger than `input`),
+    /// so `decompress_single_block` can decode it without you having to keep track of whether it was stored.
+    /// Just like there, the only setting this honors is the dictionary. The writer is left at the end of the block.
+    #[throws]
+    pub fn compress_block_at<W: Write + Seek>(&self, input: &[u8], mut writer: W, offset: u64) -> usize {
+        let (in_buffer, cursor, mut table) = self.single_block_input(input);
+        let mut block = Vec::with_capacity(compress_bound(input.len()));
+        compress2_separate_window(&in_buffer, cursor, &mut table, &mut block)?;
+        writer.seek(SeekFrom::Start(offset))?;
+        writer.write_all(&block)?;
+        block.len()
+    }
+
+    /// The dictionary followed by `input`, where `input` starts in there, and the hash table for the dictionary.
+    fn single_block_input(&self, input: &[u8]) -> (Vec<u8>, usize, U32Table) {
+        let dictionary = self.dictionary.unwrap_or(&[]);
+        let mut in_buffer = Vec::with_capacity(dictionary.len() + input.len());
+        in_buffer.extend_from_slice(dictionary);
+        in_buffer.extend_from_slice(input);
+        (in_buffer, dictionary.len(), dictionary_table(dictionary))
+    }
+
     // TODO: these interfaces need to go away in favor of something that can handle individual blocks rather than always compressing full frames at once
 
     #[throws]
     pub fn compress<R: Read, W: Write>(&self, reader: R, writer: W) {
-        self.compress_internal(reader, writer, None)?;
+        self.compress_internal(reader, writer, None, 1, |_| ())?;
+    }
+
+    /// Like `compress`, but compresses up to `threads` blocks at once on separate threads.
+    ///
+    /// The output is exactly the same as with `compress`: the blocks are read and written in order
+    /// on the calling thread, only compressing them is spread across the threads.
+    /// Up to two blocks per thread are in memory at once.
+    /// Linked blocks depend on each other, so frames with linked blocks are compressed on the calling thread.
+    #[throws]
+    pub fn compress_parallel_blocks<R: Read, W: Write>(&self, reader: R, writer: W, threads: usize) {
+        self.compress_internal(reader, writer, None, threads, |_| ())?;
+    }
+
+    /// Like `compress`, but returns how many bytes were read and written, in that order.
+    ///
+    /// The bytes written are everything that makes up the frame, including the header and checksums.
+    #[throws]
+    pub fn compress_count<R: Read, W: Write>(&self, reader: R, writer: W) -> (u64, u64) {
+        self.compress_internal(reader, writer, None, 1, |_| ())?
+    }
+
+    /// Like `compress`, but writes the frame to a file.
+    ///
+    /// The file is created if it doesn't exist and truncated if it does. Before returning, the file is synced to disk,
+    /// so write errors that would otherwise only happen when it's closed (and go unnoticed) are reported as well.
+    #[throws]
+    pub fn compress_to_file<R: Read, P: AsRef<Path>>(&self, reader: R, path: P) {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        self.compress(reader, &mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+    }
+
+    /// Like `compress`, but takes the input as a sequence of byte chunks (e.g. buffers received from a channel)
+    /// rather than a `Read`.
+    ///
+    /// The chunks are collected into blocks regardless of their size, so the output is exactly the same
+    /// as with `compress` on the concatenated input.
+    #[throws]
+    pub fn compress_chunks<I: IntoIterator, W: Write>(&self, chunks: I, writer: W) where I::Item: AsRef<[u8]> {
+        let reader = ChunkReader { chunks: chunks.into_iter(), current: None, pos: 0 };
+        self.compress_internal(reader, writer, None, 1, |_| ())?;
+    }
+
+    /// Like `compress`, but for trait objects.
+    ///
+    /// `compress` already accepts `&mut dyn Read` and `&mut dyn Write` since they implement the traits,
+    /// this just saves you the type juggling in dynamically-typed code (and compiles the compressor only once).
+    #[throws]
+    pub fn compress_dyn(&self, reader: &mut dyn Read, writer: &mut dyn Write) {
+        self.compress(reader, writer)?;
     }
 
     #[throws]
     pub fn compress_with_size_unchecked<R: Read, W: Write>(&self, reader: R, writer: W, content_size: u64) {
-        self.compress_internal(reader, writer, Some(content_size))?;
+        self.compress_internal(reader, writer, Some(content_size), 1, |_| ())?;
+    }
+
+    #[throws]
+    pub fn compress_with_size<R: Read + Seek, W: Write>(&self, reader: R, writer: W) {
+        self.compress_with_size_and_progress(reader, writer, |_, _| ())?;
     }
 
+    /// Like `compress_with_size`, but calls `progress(bytes_read_so_far, total_bytes)` after each block.
+    ///
+    /// The total is the content size that goes into the header, so this is all a progress bar needs.
     #[throws]
-    pub fn compress_with_size<R: Read + Seek, W: Write>(&self, mut reader: R, writer: W) {
+    pub fn compress_with_size_and_progress<R: Read + Seek, W: Write, F: FnMut(u64, u64)>(&self, mut reader: R, writer: W, mut progress: F) {
         // maybe one day we can just use reader.stream_len() here: https://github.com/rust-lang/rust/issues/59359
         // then again, we implement this to ignore the all bytes before the cursor which stream_len() does not
         let start = reader.seek(SeekFrom::Current(0))?;
         let end = reader.seek(SeekFrom::End(0))?;
         reader.seek(SeekFrom::Start(start))?;
 
-        let length = end - start;
-        self.compress_internal(reader, writer, Some(length))?;
+        let length = end.checked_sub(start)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "the end of the reader is before its current position"))
+            .map_err(Error::ReadError)?;
+        self.compress_internal(reader, writer, Some(length), 1, |read| progress(read, length))?;
     }
 
+    /// Returns how many bytes were read and written.
     #[throws]
-    fn compress_internal<R: Read, W: Write>(&self, mut reader: R, mut writer: W, content_size: Option<u64>) {
-        let mut content_hasher = None;
+    fn compress_internal<R: Read, W: Write, F: FnMut(u64)>(&self, reader: R, writer: W, content_size: Option<u64>, threads: usize, mut progress: F) -> (u64, u64) {
+        let mut settings = Cow::Borrowed(self);
+        let mut first_block = Vec::new();
+        let mut reader = reader.take(self.block_size as u64);
+        if self.shrink_block_size {
+            // this is what the CLI does: if the input fits into a single block, use the smallest block size that fits
+            reader.read_to_end(&mut first_block).map_err(Error::ReadError)?;
+            if first_block.len() < self.block_size {
+                let fitting = [64 * 1024, 256 * 1024, 1024 * 1024].iter().copied().find(|&size| first_block.len() <= size);
+                settings.to_mut().block_size = cmp::min(self.block_size, fitting.unwrap_or(self.block_size));
+            }
+        }
+        let mut reader = io::Cursor::new(first_block).chain(reader.into_inner());
 
-        let mut flags = Flags::empty();
-        if self.independent_blocks {
-            flags |= Flags::IndependentBlocks;
+        let mut writer = CountingWriter { inner: writer, count: 0, limit: self.max_output_bytes, limit_exceeded: false };
+        let result: Result<u64, Error> = (|| {
+            let mut frame_writer = LZ4FrameWriter::start(&mut writer, &settings, content_size)?;
+            let result = (|| {
+                if threads > 1 && settings.independent_blocks {
+                    return frame_writer.compress_parallel(&mut reader, threads, &mut progress);
+                }
+                let mut bytes_read = 0;
+                loop {
+                    let len = frame_writer.fill_from(&mut reader)?;
+                    if len == 0 {
+                        break;
+                    }
+                    frame_writer.compress_block()?;
+                    bytes_read += len as u64;
+                    progress(bytes_read);
+                }
+                Ok(bytes_read)
+            })();
+            match result {
+                Ok(bytes_read) => {
+                    frame_writer.finish()?;
+                    Ok(bytes_read)
+                }
+                Err(e) => {
+                    // dropping the writer would terminate the frame, and a valid frame would hide the missing input
+                    frame_writer.abandon();
+                    Err(e)
+                }
+            }
+        })();
+        match result {
+            Err(_) if writer.limit_exceeded => throw!(Error::OutputLimitExceeded),
+            result => (result?, writer.count),
         }
-        if self.block_checksums {
-            flags |= Flags::BlockChecksums;
+    }
+}
+
+/// Counts the bytes written to the inner writer and refuses to write more than `limit`.
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+    limit: Option<u64>,
+    /// Once set, all writes fail (so nothing follows the last complete write, not even a small one that would fit).
+    limit_exceeded: bool,
+}
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.limit_exceeded || matches!(self.limit, Some(limit) if self.count + data.len() as u64 > limit) {
+            self.limit_exceeded = true;
+            return Err(Error::OutputLimitExceeded.into());
         }
-        if self.content_checksum {
-            flags |= Flags::ContentChecksum;
-            content_hasher = Some(XxHash32::with_seed(0));
+        let written = self.inner.write(data)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads from a sequence of chunks, one chunk after another (see `CompressionSettings::compress_chunks`).
+struct ChunkReader<I: Iterator> {
+    chunks: I,
+    current: Option<I::Item>,
+    /// How much of `current` has been read already.
+    pos: usize,
+}
+impl<I: Iterator> Read for ChunkReader<I> where I::Item: AsRef<[u8]> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(chunk) = &self.current {
+                let remaining = &chunk.as_ref()[self.pos..];
+                if !remaining.is_empty() {
+                    let amt = cmp::min(remaining.len(), buf.len());
+                    buf[..amt].copy_from_slice(&remaining[..amt]);
+                    self.pos += amt;
+                    return Ok(amt);
+                }
+            }
+            // skip over empty chunks, as returning 0 would signal the end of the input
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.current = Some(chunk);
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
         }
-        if self.dictionary_id.is_some() {
-            flags |= Flags::DictionaryId;
+    }
+}
+
+/// Compresses everything you write into it into an LZ4 frame.
+///
+/// Data is buffered until a full block is available, so you must call `finish` once you're done
+/// to flush the final block and terminate the frame. If you simply drop the writer, it tries
+/// to finish the frame but ignores any errors that occur while doing so.
+///
+/// Memory usage is fixed when the writer is created: two buffers of the block size, plus up to 64 KiB
+/// for the window (linked blocks) or the dictionary, plus the hash tables (2 * 16 KiB).
+pub struct LZ4FrameWriter<'a, W: Write> {
+    writer: Option<W>, // None once we are finished or abandoned
+    settings: CompressionSettings<'a>,
+    flags: Flags,
+    content_hasher: Option<ChecksumHasher>,
+    #[cfg(feature = "xxhash64")]
+    content_hasher64: Option<XxHash64>,
+    template_table: U32Table,
+    table: U32Table,
+    /// The dictionary or (with linked blocks) the window, followed by the data of the current block.
+    in_buffer: Vec<u8>,
+    /// Where the data of the current block starts in `in_buffer`.
+    window_offset: usize,
+    out_buffer: Vec<u8>,
+    blocks_written: u64,
+    /// How many of the blocks written so far were stored rather than compressed.
+    blocks_stored: u64,
+    /// Set while a block is being written, so it stays set if that fails. The frame is broken at that point
+    /// and our state is inconsistent (e.g. the hash table already contains the block), so we refuse to continue.
+    failed: bool,
+}
+
+impl<'a, W: Write> LZ4FrameWriter<'a, W> {
+    /// Create a new frame writer and write the frame header right away.
+    ///
+    /// The header says whether the blocks are linked, so choose that through `CompressionSettings::linked_blocks`
+    /// or `unlinked_blocks` beforehand: once the writer exists, it can't be changed anymore.
+    #[throws]
+    pub fn new(writer: W, settings: &CompressionSettings<'a>) -> Self {
+        Self::start(writer, settings, None)?
+    }
+
+    #[throws]
+    pub(super) fn start(mut writer: W, settings: &CompressionSettings<'a>, content_size: Option<u64>) -> Self {
+        let mut content_hasher = None;
+
+        if !settings.block_checksums && !settings.content_checksum && !settings.independent_blocks {
+            // a single flipped bit in a linked block silently corrupts every block after it
+            log::warn!("compressing a frame with linked blocks but neither block nor content checksums, \
+                corruption will go undetected; consider enabling at least the content checksum");
         }
-        if content_size.is_some() {
-            flags |= Flags::ContentSize;
+
+        let mut flags = Flags::empty();
+        flags.set_independent_blocks(settings.independent_blocks);
+        flags.set_block_checksums(settings.block_checksums);
+        flags.set_content_checksum(settings.content_checksum);
+        flags.set_dictionary_id(settings.dictionary_id.is_some());
+        flags.set_content_size(content_size.is_some());
+        if settings.content_checksum {
+            content_hasher = Some(settings.checksum_hasher());
         }
 
         let version = 1 << 6;
         let flag_byte = version | flags.bits();
-        let bd_byte = BlockDescriptor::new(self.block_size).ok_or(Error::InvalidBlockSize)?.0;
+        let bd_byte = BlockDescriptor::new(settings.block_size).ok_or(Error::InvalidBlockSize)?.0;
 
         let mut header = Vec::new();
         header.write_u32::<LE>(MAGIC)?;
@@ -190,7 +774,7 @@ impl<'a> CompressionSettings<'a> {
         if let Some(content_size) = content_size {
             header.write_u64::<LE>(content_size)?;
         }
-        if let Some(id) = self.dictionary_id {
+        if let Some(id) = settings.dictionary_id {
             header.write_u32::<LE>(id)?;
         }
 
@@ -199,119 +783,966 @@ impl<'a> CompressionSettings<'a> {
         header.write_u8((hasher.finish() >> 8) as u8)?;
         writer.write_all(&header)?;
 
-        let mut template_table = U32Table::default();
-        let mut block_initializer: &[u8] = &[];
-        if let Some(dict) = self.dictionary {
-            for window in dict.windows(mem::size_of::<usize>()).step_by(3) {
-                // this is a perfectly safe way to find out where our window is pointing
-                // we could do this manually by iterating with an index to avoid the scary-looking
-                // pointer math but this is way more convenient IMO
-                let offset = window.as_ptr() as usize - dict.as_ptr() as usize;
-                template_table.replace(dict, offset);
+        // anything before the trailing 64 KiB of the dictionary can never be referenced
+        let dictionary = settings.dictionary.unwrap_or(&[]);
+        let dictionary = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+        let template_table = dictionary_table(dictionary);
+
+        // allocate everything we'll ever need right away: the window (or dictionary) plus one block
+        let window_size = if settings.independent_blocks { dictionary.len() } else { WINDOW_SIZE };
+        let mut in_buffer = Vec::with_capacity(window_size + settings.block_size);
+        in_buffer.extend_from_slice(dictionary);
+
+        LZ4FrameWriter {
+            writer: Some(writer),
+            settings: settings.clone(),
+            flags,
+            content_hasher,
+            #[cfg(feature = "xxhash64")]
+            content_hasher64: if settings.xxhash64_checksum { Some(XxHash64::with_seed(0)) } else { None },
+            table: template_table.clone(),
+            template_table,
+            window_offset: in_buffer.len(),
+            in_buffer,
+            out_buffer: vec![0u8; settings.block_size],
+            blocks_written: 0,
+            blocks_stored: 0,
+            failed: false,
+        }
+    }
+
+    /// How many uncompressed bytes are waiting for the current block to fill up (or for `flush`).
+    ///
+    /// As a full block is compressed right away, this is always less than the block size.
+    pub fn bytes_buffered(&self) -> usize {
+        self.in_buffer.len() - self.window_offset
+    }
+
+    /// Add as much of `buf` to the current block as fits, compressing the block once it's full.
+    #[throws]
+    fn push(&mut self, buf: &[u8]) -> usize {
+        let missing = self.settings.block_size - self.bytes_buffered();
+        let amt = cmp::min(missing, buf.len());
+        self.in_buffer.extend_from_slice(&buf[..amt]);
+        if amt == missing {
+            self.compress_block()?;
+        }
+        amt
+    }
+
+    /// Like `write_all`, but without wrapping errors into `io::Error`.
+    #[throws]
+    pub(super) fn push_all(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let amt = self.push(buf)?;
+            buf = &buf[amt..];
+        }
+    }
+
+    /// Read from `reader` until the current block is full or the reader is exhausted.
+    #[throws]
+    fn fill_from<R: Read>(&mut self, reader: R) -> usize {
+        let missing = self.settings.block_size - self.bytes_buffered();
+
+        // We basically want read_exact semantics, except at the end.
+        // Sadly read_exact specifies the buffer contents to be undefined
+        // on error, so we have to use this construction instead.
+        reader.take(missing as u64).read_to_end(&mut self.in_buffer).map_err(Error::ReadError)?
+    }
+
+    /// Compress and write out the current block, if there is any data in it.
+    #[throws]
+    fn compress_block(&mut self) {
+        if self.failed {
+            throw!(Error::WriteError(io::Error::other("writing a block failed earlier, the frame is incomplete")));
+        }
+        if self.bytes_buffered() == 0 {
+            return;
+        }
+        self.failed = true;
+        let window_offset = self.window_offset;
+
+        // take the buffers out of self so we can hand them to write_block
+        let in_buffer = mem::take(&mut self.in_buffer);
+        let mut out_buffer = mem::take(&mut self.out_buffer);
+        let compressed_len = compress_or_store(&in_buffer, window_offset, &mut self.table, &mut out_buffer);
+        let result = self.write_block(&in_buffer[window_offset..], compressed_len.map(|len| &out_buffer[..len]));
+        self.in_buffer = in_buffer;
+        self.out_buffer = out_buffer;
+        result?;
+
+        if self.flags.independent_blocks() {
+            // clear table
+            self.in_buffer.truncate(window_offset);
+            self.table = self.template_table.clone();
+        } else {
+            if self.in_buffer.len() > WINDOW_SIZE {
+                let how_much_to_forget = self.in_buffer.len() - WINDOW_SIZE;
+                self.table.offset(how_much_to_forget);
+                self.in_buffer.drain(..how_much_to_forget);
             }
+            self.window_offset = self.in_buffer.len();
+        }
+        self.failed = false;
+    }
 
-            block_initializer = dict;
+    /// Write out a block, compressed unless `compressed` is `None`.
+    #[throws]
+    fn write_block(&mut self, data: &[u8], compressed: Option<&[u8]>) {
+        let writer = self.writer.as_mut().expect("LZ4FrameWriter used after finish");
+        if let Some(x) = self.content_hasher.as_mut() {
+            x.write(data);
+        }
+        #[cfg(feature = "xxhash64")]
+        if let Some(x) = self.content_hasher64.as_mut() {
+            x.write(data);
         }
 
-        // TODO: when doing dependent blocks or dictionaries, in_buffer's capacity is insufficient
-        let mut in_buffer = Vec::with_capacity(self.block_size);
-        in_buffer.extend_from_slice(block_initializer);
-        let mut out_buffer = vec![0u8; self.block_size];
-        let mut table = template_table.clone();
-        loop {
-            let window_offset = in_buffer.len();
-
-            // We basically want read_exact semantics, except at the end.
-            // Sadly read_exact specifies the buffer contents to be undefined
-            // on error, so we have to use this construction instead.
-            reader.by_ref().take(self.block_size as u64).read_to_end(&mut in_buffer).map_err(Error::ReadError)?;
-            let read_bytes = in_buffer.len() - window_offset;
-            if read_bytes == 0 {
-                break;
+        let write = match compressed {
+            Some(compressed) => {
+                writer.write_u32::<LE>(block_length_field(compressed.len(), false))?;
+                compressed
             }
-            
-            if let Some(x) = content_hasher.as_mut() {
-                x.write(&in_buffer[window_offset..]);
+            None => {
+                writer.write_u32::<LE>(block_length_field(data.len(), true))?;
+                self.blocks_stored += 1;
+                data
             }
+        };
+        self.blocks_written += 1;
 
-            // TODO: implement u16 table for small inputs
-
-            // 1. limit output by input size so we never have negative compression ratio
-            // 2. use a wrapper that forbids partial writes, so don't write 32-bit integers
-            //    as four individual bytes with four individual range checks
-            let mut cursor = NoPartialWrites(&mut out_buffer[..read_bytes]);
-            let write = match compress2(&in_buffer, window_offset, &mut table, &mut cursor) {
-                Ok(()) => {
-                    let not_written_len = cursor.0.len();
-                    let written_len = read_bytes - not_written_len;
-                    writer.write_u32::<LE>(written_len as u32)?;
-                    &out_buffer[..written_len]
+        writer.write_all(write)?;
+        if self.flags.block_checksums() {
+            let mut block_hasher = self.settings.checksum_hasher();
+            block_hasher.write(write);
+            writer.write_u32::<LE>(block_hasher.finish() as u32)?;
+        }
+    }
+
+    /// Compress everything from `reader` with independent blocks, `threads` blocks at once.
+    ///
+    /// Each block is compressed just like `compress_block` would, so the output is the same.
+    /// Calls `progress` with the bytes read so far after each block and returns how many bytes were read.
+    #[throws]
+    fn compress_parallel<R: Read, F: FnMut(u64)>(&mut self, mut reader: R, threads: usize, mut progress: F) -> u64 {
+        assert!(self.flags.independent_blocks() && self.bytes_buffered() == 0);
+        if self.failed {
+            throw!(Error::WriteError(io::Error::other("writing a block failed earlier, the frame is incomplete")));
+        }
+        // every block is read into a copy of the dictionary so the threads can compress it in place
+        let dictionary = self.in_buffer.clone();
+        let template_table = self.template_table.clone();
+        let block_size = self.settings.block_size;
+
+        let (work_sender, work_receiver) = mpsc::channel::<(u64, Vec<u8>)>();
+        let (done_sender, done_receiver) = mpsc::channel();
+        let work_receiver = Mutex::new(work_receiver);
+        self.failed = true;
+        let bytes_read = thread::scope(|scope| -> Result<u64, Error> {
+            for _ in 0..threads {
+                let work_receiver = &work_receiver;
+                let done_sender = done_sender.clone();
+                let (dictionary, template_table) = (&dictionary, &template_table);
+                scope.spawn(move || loop {
+                    // the lock is only held while waiting for work, not while compressing
+                    let work = work_receiver.lock().expect("a compression thread panicked").recv();
+                    match work {
+                        Ok((index, block)) => {
+                            let mut out_buffer = vec![0u8; block.len() - dictionary.len()];
+                            let compressed = compress_or_store(&block, dictionary.len(), &mut template_table.clone(), &mut out_buffer)
+                                .map(|len| {
+                                    out_buffer.truncate(len);
+                                    out_buffer
+                                });
+                            if done_sender.send((index, block, compressed)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(_) => break, // no more blocks
+                    }
+                });
+            }
+            drop(done_sender);
+
+            // returning (even with an error) drops work_sender, which makes the threads exit
+            let mut work_sender = Some(work_sender);
+            let mut blocks_read = 0;
+            let mut blocks_written = 0;
+            let mut finished_blocks = BTreeMap::new();
+            let mut bytes_read = 0;
+            loop {
+                while let Some(sender) = work_sender.as_ref().filter(|_| blocks_read - blocks_written < 2 * threads as u64) {
+                    let mut block = dictionary.clone();
+                    let len = (&mut reader).take(block_size as u64).read_to_end(&mut block).map_err(Error::ReadError)?;
+                    if len == 0 {
+                        work_sender = None;
+                        break;
+                    }
+                    sender.send((blocks_read, block)).expect("all compression threads panicked");
+                    blocks_read += 1;
                 }
-                Err(e) => {
-                    assert!(e.kind() == ErrorKind::ConnectionAborted);
-                    // incompressible
-                    writer.write_u32::<LE>((read_bytes as u32) | INCOMPRESSIBLE)?;
-                    &in_buffer[window_offset..]
+                if blocks_written == blocks_read {
+                    break;
                 }
-            };
 
-            writer.write_all(write)?;
-            if flags.contains(Flags::BlockChecksums) {
-                let mut block_hasher = XxHash32::with_seed(0);
-                block_hasher.write(write);
-                writer.write_u32::<LE>(block_hasher.finish() as u32)?;
+                // blocks can finish out of order, but we must write them in order
+                let (block, compressed) = loop {
+                    if let Some(block) = finished_blocks.remove(&blocks_written) {
+                        break block;
+                    }
+                    let (index, block, compressed) = done_receiver.recv().expect("a compression thread panicked");
+                    finished_blocks.insert(index, (block, compressed));
+                };
+                self.write_block(&block[dictionary.len()..], compressed.as_deref())?;
+                bytes_read += (block.len() - dictionary.len()) as u64;
+                blocks_written += 1;
+                progress(bytes_read);
             }
+            Ok(bytes_read)
+        })?;
+        self.failed = false;
+        bytes_read
+    }
+
+    #[throws]
+    fn write_end(&mut self) {
+        self.compress_block()?;
+
+        let writer = self.writer.as_mut().expect("LZ4FrameWriter used after finish");
+        writer.write_u32::<LE>(0)?;
+        if let Some(x) = self.content_hasher.take() {
+            writer.write_u32::<LE>(x.finish() as u32)?;
+        }
+        #[cfg(feature = "xxhash64")]
+        if let Some(x) = self.content_hasher64.take() {
+            writer.write_u32::<LE>(XXH64_FRAME_MAGIC)?;
+            writer.write_u32::<LE>((XXH64_FRAME_TAG.len() + 8) as u32)?;
+            writer.write_all(&XXH64_FRAME_TAG)?;
+            writer.write_u64::<LE>(x.finish())?;
+        }
 
-            if flags.contains(Flags::IndependentBlocks) {
-                // clear table
-                in_buffer.clear();
-                in_buffer.extend_from_slice(block_initializer);
-
-                table = template_table.clone();
-            } else {
-                if in_buffer.len() > WINDOW_SIZE {
-                    let how_much_to_forget = in_buffer.len() - WINDOW_SIZE;
-                    table.offset(how_much_to_forget);
-                    in_buffer.drain(..how_much_to_forget);
+        if let Some((threshold, hook)) = self.settings.incompressible_hook.as_ref() {
+            if self.blocks_written != 0 {
+                let ratio = self.blocks_stored as f32 / self.blocks_written as f32;
+                if ratio > *threshold {
+                    hook(ratio);
                 }
             }
         }
-        writer.write_u32::<LE>(0)?;
+    }
 
-        if let Some(x) = content_hasher {
-            writer.write_u32::<LE>(x.finish() as u32)?;
+    /// Compress any remaining data, terminate the frame and return the underlying writer.
+    #[throws]
+    pub fn finish(mut self) -> W {
+        let result = self.write_end();
+        let writer = self.writer.take().expect("LZ4FrameWriter used after finish");
+        result?;
+        writer
+    }
+
+    /// Give up on this frame and return the underlying writer without writing anything else.
+    ///
+    /// Any data that is still buffered is discarded and the frame is never terminated,
+    /// so whatever has been written so far is **not** a valid LZ4 frame and can not be decompressed.
+    /// This is intended for error paths where you don't want to do any more I/O.
+    pub fn abandon(mut self) -> W {
+        self.writer.take().expect("LZ4FrameWriter used after finish")
+    }
+}
+
+impl<'a> LZ4FrameWriter<'a, Vec<u8>> {
+    /// Create a frame writer that compresses into memory.
+    ///
+    /// Use `into_compressed` to get the frame once you're done.
+    #[throws]
+    pub fn to_vec(settings: &CompressionSettings<'a>) -> Self {
+        Self::new(Vec::new(), settings)?
+    }
+
+    /// Terminate the frame and return the compressed data (just like `finish`, but with an `io::Error`).
+    #[throws(io::Error)]
+    pub fn into_compressed(self) -> Vec<u8> {
+        self.finish()?
+    }
+}
+
+impl<W: Write> Write for LZ4FrameWriter<'_, W> {
+    #[throws(io::Error)]
+    fn write(&mut self, buf: &[u8]) -> usize {
+        self.push(buf)?
+    }
+
+    /// Compress whatever is buffered right now into a (smaller than usual) block and flush the underlying writer.
+    ///
+    /// This makes all data written so far available to the receiver without waiting for the block to fill up,
+    /// which is what you want for low-latency streaming. With linked blocks, later blocks can still reference
+    /// this data. However, every flush ends a block early, so flushing frequently hurts the compression ratio.
+    #[throws(io::Error)]
+    fn flush(&mut self) {
+        self.compress_block()?;
+        self.writer.as_mut().expect("LZ4FrameWriter used after finish").flush()?;
+    }
+}
+
+impl<W: Write> Drop for LZ4FrameWriter<'_, W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() && !self.failed {
+            let _ = self.write_end();
         }
     }
 }
 
-/// Helper struct to allow more efficient code generation when using the Write trait on byte buffers.
+/// The length field of a block: its length, with the `INCOMPRESSIBLE` bit set if it is stored.
 ///
-/// The underlying problem is that the Write impl on [u8] (and everything similar, e.g. Cursor<[u8]>)
-/// is specified to write as many bytes as possible before returning an error.
-/// This is a problem because it forces e.g. a 32-bit write to compile to four 8-bit writes with a range
-/// check every time, rather than a single 32-bit write with a range check.
-///
-/// This wrapper aims to resolve the problem by simply not writing anything in case we fail the bounds check,
-/// as we throw away the entire buffer in that case anyway.
-struct NoPartialWrites<'a>(&'a mut [u8]);
-impl<'a> Write for NoPartialWrites<'a> {
-    #[inline]
-    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
-        if self.0.len() < data.len() {
-            // quite frankly it doesn't matter what we specify here
-            return Err(ErrorKind::ConnectionAborted.into());
+/// Lengths must stay below 2 GiB so they can't collide with that bit. Blocks are at most 4 MiB
+/// (larger block sizes fail with `InvalidBlockSize`), but we don't want to silently write garbage if that ever changes.
+fn block_length_field(len: usize, stored: bool) -> u32 {
+    assert!(len < INCOMPRESSIBLE as usize, "a block length of {} bytes collides with the INCOMPRESSIBLE bit", len);
+    len as u32 | if stored { INCOMPRESSIBLE } else { 0 }
+}
+
+/// Build the hash table that lets the first block reference the dictionary.
+fn dictionary_table(dict: &[u8]) -> U32Table {
+    let mut table = U32Table::default();
+    for window in dict.windows(mem::size_of::<usize>()).step_by(3) {
+        // this is a perfectly safe way to find out where our window is pointing
+        // we could do this manually by iterating with an index to avoid the scary-looking
+        // pointer math but this is way more convenient IMO
+        let offset = window.as_ptr() as usize - dict.as_ptr() as usize;
+        table.replace(dict, offset);
+    }
+    table
+}
+
+/// Compress `input[cursor..]` into `out_buffer`, returning the compressed length,
+/// or `None` if the block is incompressible and should be stored as-is instead.
+fn compress_or_store(input: &[u8], cursor: usize, table: &mut U32Table, out_buffer: &mut [u8]) -> Option<usize> {
+    // TODO: implement u16 table for small inputs
+
+    // limit output by input size so we never have negative compression ratio
+    let read_bytes = input.len() - cursor;
+    compress_separate_window_into(input, cursor, table, &mut out_buffer[..read_bytes]).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::hash::Hasher;
+    use std::io::{ErrorKind, Read, Write};
+    use super::{BlockSize, CompressionError, CompressionSettings, LZ4FrameWriter, ParamsParseError, block_length_field};
+    use crate::test_data::noise;
+    use crate::framed::{decompress_frame, decompress_frame_to_writer, decompress_single_block, DecompressionError, LZ4FrameReader};
+    use crate::raw::decompress_raw;
+
+    fn plaintext() -> Vec<u8> {
+        (0..300_000u32).map(|i| (i % 253) as u8 ^ (i / 4096) as u8).collect()
+    }
+
+    #[test]
+    fn writer_matches_compress() {
+        for &independent in &[true, false] {
+            let mut settings = CompressionSettings::default();
+            settings.block_size(64 * 1024);
+            if !independent {
+                settings.linked_blocks();
+            }
+            let plaintext = plaintext();
+
+            let mut expected = Vec::new();
+            settings.compress(plaintext.as_slice(), &mut expected).unwrap();
+
+            let mut writer = settings.streaming_compress(Vec::new()).unwrap();
+            for chunk in plaintext.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+            }
+            let compressed = writer.finish().unwrap();
+            assert!(compressed == expected);
         }
+    }
+
+    #[test]
+    fn flush_emits_block() {
+        let message = b"a chat message that is sent twice: hello, hello!";
+        let mut writer = LZ4FrameWriter::new(Vec::new(), CompressionSettings::default().linked_blocks()).unwrap();
+        writer.write_all(message).unwrap();
+        writer.flush().unwrap();
+
+        // the receiver can decode the first message before the frame is finished
+        let sent = writer.writer.as_ref().unwrap().clone();
+        let mut reader = LZ4FrameReader::new(sent.as_slice()).unwrap();
+        let mut block = Vec::new();
+        reader.decode_block(&mut block, &[]).unwrap();
+        assert_eq!(block, &message[..]);
+
+        writer.write_all(message).unwrap();
+        writer.flush().unwrap();
+        let second_block_len = writer.writer.as_ref().unwrap().len() - sent.len();
+        // the second block just references the first one
+        assert!(second_block_len < 20);
 
-        let amt = data.len();
-        let (a, b) = mem::replace(&mut self.0, &mut []).split_at_mut(data.len());
-        a.copy_from_slice(data);
-        self.0 = b;
-        Ok(amt)
+        let compressed = writer.finish().unwrap();
+        assert_eq!(decompress_frame(compressed.as_slice()).unwrap(), [&message[..], &message[..]].concat());
     }
 
-    #[inline]
-    fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+    #[test]
+    fn bytes_buffered() {
+        let plaintext = plaintext();
+        for &linked in &[false, true] {
+            let mut settings = CompressionSettings::default();
+            settings.block_size(64 * 1024).dictionary(1, b"dictionary");
+            if linked {
+                settings.linked_blocks();
+            }
+            let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+            assert_eq!(writer.bytes_buffered(), 0);
+            writer.write_all(&plaintext[..1000]).unwrap();
+            assert_eq!(writer.bytes_buffered(), 1000);
+            // exactly fills the block
+            writer.write_all(&plaintext[1000..64 * 1024]).unwrap();
+            assert_eq!(writer.bytes_buffered(), 0);
+            writer.write_all(&plaintext[64 * 1024..][..10]).unwrap();
+            assert_eq!(writer.bytes_buffered(), 10);
+            writer.flush().unwrap();
+            assert_eq!(writer.bytes_buffered(), 0);
+        }
+    }
+
+    #[test]
+    fn streaming_writes_header_immediately() {
+        let mut compressed = Vec::new();
+        let writer = CompressionSettings::default().streaming_compress(&mut compressed).unwrap();
+        writer.abandon();
+        assert_eq!(compressed[..4], [0x04, 0x22, 0x4D, 0x18]);
+    }
+
+    #[test]
+    fn drop_finishes_frame() {
+        let mut compressed = Vec::new();
+        LZ4FrameWriter::new(&mut compressed, &CompressionSettings::default()).unwrap().write_all(b"hello").unwrap();
+        assert_eq!(decompress_frame(compressed.as_slice()).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn abandon_writes_nothing_more() {
+        let mut writer = LZ4FrameWriter::new(Vec::new(), &CompressionSettings::default()).unwrap();
+        writer.write_all(b"never to be seen again").unwrap();
+        let compressed = writer.abandon();
+        // just the header
+        assert_eq!(compressed.len(), 7);
+        assert!(decompress_frame(compressed.as_slice()).is_err());
+    }
+
+    #[test]
+    fn no_backtracking_into_window() {
+        // the match at the second "abc..." could be extended backwards by one byte into the dictionary,
+        // but the reference implementation doesn't do that: 17 literals (15 + 2), then the match
+        let input = b"abcdefghijklmnopZabcdefghijklmnop, followed by some literals";
+        let (compressed, block) = CompressionSettings::default().dictionary(0, b"xxxxZ").compress_single_block(input);
+        assert!(compressed);
+        assert_eq!(&block[..2], &[0xfc, 2]);
+        let mut output = Vec::new();
+        decompress_raw(&block, b"xxxxZ", &mut output, input.len()).unwrap();
+        assert_eq!(&output[..], &input[..]);
+    }
+
+    #[test]
+    fn single_block() {
+        let plaintext = plaintext();
+        let dictionary = &plaintext[..1000];
+        let input = &plaintext[1000..70_000];
+
+        let (compressed, block) = CompressionSettings::default().compress_single_block(input);
+        assert!(compressed);
+        let mut output = Vec::new();
+        decompress_raw(&block, &[], &mut output, input.len()).unwrap();
+        assert_eq!(output, input);
+
+        let (compressed, dict_block) = CompressionSettings::default().dictionary(0, dictionary).compress_single_block(input);
+        assert!(compressed && dict_block.len() < block.len());
+        output.clear();
+        decompress_raw(&dict_block, dictionary, &mut output, input.len()).unwrap();
+        assert_eq!(output, input);
+
+        let noise = noise(1000);
+        assert_eq!(CompressionSettings::default().compress_single_block(&noise), (false, noise.clone()));
+    }
+
+    #[test]
+    fn length_field() {
+        assert_eq!(block_length_field(0, false), 0);
+        assert_eq!(block_length_field(4 * 1024 * 1024, true), 0x8040_0000);
+        assert_eq!(block_length_field(0x7FFF_FFFF, false), 0x7FFF_FFFF);
+    }
+
+    #[test]
+    #[should_panic(expected = "collides with the INCOMPRESSIBLE bit")]
+    fn length_field_overflow() {
+        block_length_field(0x8000_0000, false);
+    }
+
+    #[test]
+    fn block_at() {
+        let plaintext = plaintext();
+        let noise = noise(1000);
+        let mut file = std::io::Cursor::new(vec![0xAA; 200]);
+
+        let len = CompressionSettings::default().compress_block_at(&plaintext[..10_000], &mut file, 100).unwrap();
+        assert_eq!(file.position(), 100 + len as u64);
+        let file = file.into_inner();
+        assert!(file[..100].iter().all(|&b| b == 0xAA) && len < 10_000);
+        assert_eq!(decompress_single_block(&file[100..][..len], &[], 10_000).unwrap(), &plaintext[..10_000]);
+
+        // incompressible data is still written as a (slightly larger) compressed block
+        let mut file = std::io::Cursor::new(Vec::new());
+        let len = CompressionSettings::default().compress_block_at(&noise, &mut file, 3).unwrap();
+        assert!(len > noise.len());
+        assert_eq!(decompress_single_block(&file.get_ref()[3..], &[], noise.len()).unwrap(), noise);
+    }
+
+    #[test]
+    fn compress_dyn() {
+        let plaintext = plaintext();
+        let mut expected = Vec::new();
+        CompressionSettings::default().compress(plaintext.as_slice(), &mut expected).unwrap();
+        let mut compressed = Vec::new();
+        CompressionSettings::default().compress_dyn(&mut plaintext.as_slice(), &mut compressed).unwrap();
+        assert_eq!(compressed, expected);
+    }
+
+    #[test]
+    fn auto_block_size() {
+        assert_eq!(BlockSize::recommended_for(0), BlockSize::Max64KB);
+        assert_eq!(BlockSize::recommended_for(2000), BlockSize::Max64KB);
+        assert_eq!(BlockSize::recommended_for(256 * 1024), BlockSize::Max256KB);
+        assert_eq!(BlockSize::recommended_for(3 * 1024 * 1024), BlockSize::Max1MB);
+        assert_eq!(BlockSize::recommended_for(100 * 1024 * 1024), BlockSize::Max4MB);
+
+        let plaintext = plaintext();
+        let mut compressed = Vec::new();
+        CompressionSettings::default().auto_block_size(plaintext.len()).compress(plaintext.as_slice(), &mut compressed).unwrap();
+        assert_eq!(LZ4FrameReader::new(compressed.as_slice()).unwrap().block_size(), Some(256 * 1024));
+        assert_eq!(decompress_frame(compressed.as_slice()).unwrap(), plaintext);
     }
-}
 
+    #[test]
+    fn to_vec() {
+        let plaintext = plaintext();
+        let mut writer = LZ4FrameWriter::to_vec(&CompressionSettings::default()).unwrap();
+        for chunk in plaintext.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        let compressed = writer.into_compressed().unwrap();
+
+        let mut expected = Vec::new();
+        CompressionSettings::default().compress(plaintext.as_slice(), &mut expected).unwrap();
+        assert_eq!(compressed, expected);
+    }
+
+    #[test]
+    fn to_file() {
+        let plaintext = plaintext();
+        let mut expected = Vec::new();
+        CompressionSettings::default().compress(plaintext.as_slice(), &mut expected).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("compressed.lz4");
+        CompressionSettings::default().compress_to_file(plaintext.as_slice(), &path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+
+        let e = CompressionSettings::default().compress_to_file(plaintext.as_slice(), dir.path().join("missing/compressed.lz4")).unwrap_err();
+        assert!(matches!(e, CompressionError::WriteError(ref e) if e.kind() == ErrorKind::NotFound), "{:?}", e);
+    }
+
+    #[test]
+    fn compress_chunks() {
+        let plaintext = plaintext();
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024);
+        let mut expected = Vec::new();
+        settings.compress(plaintext.as_slice(), &mut expected).unwrap();
+
+        for &chunk_size in &[1000, 64 * 1024, 100_000] {
+            let mut compressed = Vec::new();
+            settings.compress_chunks(plaintext.chunks(chunk_size), &mut compressed).unwrap();
+            assert_eq!(compressed, expected);
+        }
+
+        // owned chunks, including empty ones
+        let chunks = vec![Vec::new(), plaintext[..5].to_vec(), Vec::new(), plaintext[5..].to_vec()];
+        let mut compressed = Vec::new();
+        settings.compress_chunks(chunks, &mut compressed).unwrap();
+        assert_eq!(compressed, expected);
+    }
+
+    #[test]
+    fn parallel_blocks() {
+        let plaintext = plaintext();
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).block_checksums(true).dictionary(7, b"some dictionary");
+        for &threads in &[0, 1, 3] {
+            for &linked in &[false, true] {
+                if linked { settings.linked_blocks(); } else { settings.unlinked_blocks(); }
+                let mut expected = Vec::new();
+                settings.compress(plaintext.as_slice(), &mut expected).unwrap();
+                let mut compressed = Vec::new();
+                settings.compress_parallel_blocks(plaintext.as_slice(), &mut compressed, threads).unwrap();
+                assert_eq!(compressed, expected);
+            }
+        }
+
+        // empty input and write errors
+        let mut compressed = Vec::new();
+        CompressionSettings::default().compress_parallel_blocks(&[][..], &mut compressed, 2).unwrap();
+        assert_eq!(decompress_frame(compressed.as_slice()).unwrap(), b"");
+        let mut buffer = [0u8; 1000];
+        assert!(settings.unlinked_blocks().compress_parallel_blocks(plaintext.as_slice(), &mut buffer[..], 2).is_err());
+    }
+
+    #[test]
+    fn incompressible_ratio() {
+        let noise = noise(3 * 64 * 1024);
+        let mut input = plaintext()[..64 * 1024].to_vec();
+        input.extend_from_slice(&noise);
+
+        let ratios = std::sync::Mutex::new(Vec::new());
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).on_incompressible_ratio(0.5, |ratio| ratios.lock().unwrap().push(ratio));
+        settings.compress(input.as_slice(), std::io::sink()).unwrap();
+        settings.compress(plaintext().as_slice(), std::io::sink()).unwrap();
+        settings.compress(&[][..], std::io::sink()).unwrap();
+        let mut writer = settings.streaming_compress(std::io::sink()).unwrap();
+        writer.write_all(&noise).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(*ratios.lock().unwrap(), [0.75, 1.0]);
+
+        // hooks are compared by identity
+        assert_eq!(settings.clone(), settings);
+        let mut other = settings.clone();
+        assert_ne!(other.on_incompressible_ratio(0.5, |_| ()), &settings);
+    }
+
+    #[test]
+    fn buffer_capacity_stays_fixed() {
+        let plaintext = plaintext();
+        let dictionary = &plaintext[..100_000];
+        for &independent in &[true, false] {
+            let mut settings = CompressionSettings::default();
+            settings.block_size(64 * 1024).dictionary(1, dictionary);
+            if !independent {
+                settings.linked_blocks();
+            }
+            let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+            let capacity = writer.in_buffer.capacity();
+            assert!(capacity <= 2 * 64 * 1024);
+            for chunk in plaintext.chunks(10_000) {
+                writer.write_all(chunk).unwrap();
+                assert_eq!(writer.in_buffer.capacity(), capacity);
+            }
+            let compressed = writer.finish().unwrap();
+
+            let mut output = Vec::new();
+            let reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+            std::io::Read::read_to_end(&mut reader.into_read_with_dictionary(dictionary), &mut output).unwrap();
+            assert!(output == plaintext);
+        }
+    }
+
+    #[test]
+    fn dictionary_id_flag() {
+        let dict = b"the dictionary, the dictionary, the dictionary";
+        let plaintext = b"not the dictionary, not the dictionary";
+        let frame = |settings: &CompressionSettings| {
+            let mut output = Vec::new();
+            settings.compress(&plaintext[..], &mut output).unwrap();
+            let reader = LZ4FrameReader::new(output.as_slice()).unwrap();
+            let (flag, id) = (output[4] & 1 != 0, reader.dictionary_id());
+            let mut decompressed = Vec::new();
+            reader.into_read_with_dictionary(dict).read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, plaintext);
+            (flag, id)
+        };
+
+        // the flag is set exactly when the id is written (and the header parses, so the lengths line up)
+        assert_eq!(frame(CompressionSettings::default().dictionary(7, dict)), (true, Some(7)));
+        assert_eq!(frame(CompressionSettings::default().dictionary(7, dict).dictionary_id_nonsense_override(None)), (false, None));
+        assert_eq!(frame(CompressionSettings::default().dictionary_id_nonsense_override(Some(7))), (true, Some(7)));
+        assert_eq!(frame(&CompressionSettings::default()), (false, None));
+
+        let mut hasher = twox_hash::XxHash32::with_seed(0);
+        hasher.write(dict);
+        assert_eq!(frame(CompressionSettings::default().dictionary_auto_id(dict)), (true, Some(hasher.finish() as u32)));
+    }
+
+    /// Always hashes to the same value, no matter the input.
+    struct FixedHasher;
+    impl Hasher for FixedHasher {
+        fn write(&mut self, _: &[u8]) {}
+        fn finish(&self) -> u64 { 0x1234_5678 }
+    }
+
+    #[test]
+    fn mock_hasher() {
+        let plaintext = plaintext();
+        let mut output = Vec::new();
+        CompressionSettings::default().block_size(256 * 1024).block_checksums(true).with_hasher(|| Box::new(FixedHasher))
+            .compress(plaintext.as_slice(), &mut output).unwrap();
+
+        // both blocks and the content end with our fixed checksum
+        let checksum = 0x1234_5678u32.to_le_bytes();
+        assert_eq!(output[output.len() - 4..], checksum);
+        assert_eq!(output[output.len() - 12..][..4], checksum);
+        let first_block_len = u32::from_le_bytes([output[7], output[8], output[9], output[10]]) as usize & 0x7FFF_FFFF;
+        assert_eq!(output[11 + first_block_len..][..4], checksum);
+
+        // the header checksum is still right, only the others are wrong
+        let mut reader = LZ4FrameReader::new(output.as_slice()).unwrap();
+        assert!(matches!(reader.decode_block(&mut Vec::new(), &[]), Err(DecompressionError::BlockChecksumFail)));
+    }
+
+    #[test]
+    fn content_checksum_per_frame() {
+        let settings = CompressionSettings::default();
+        let mut checksums = Vec::new();
+        for _ in 0..2 {
+            for part in &[&b"first frame"[..], b"second frame"] {
+                let mut output = Vec::new();
+                settings.compress(*part, &mut output).unwrap();
+                checksums.push(output[output.len() - 4..].to_vec());
+
+                let mut hasher = twox_hash::XxHash32::with_seed(0);
+                hasher.write(part);
+                assert_eq!(output[output.len() - 4..], (hasher.finish() as u32).to_le_bytes());
+            }
+        }
+        assert_eq!(checksums[..2], checksums[2..]);
+    }
+
+    #[test]
+    fn params_str() {
+        assert_eq!(CompressionSettings::default().to_params_str(), "LZ4f,block=4M,indep,csum");
+        let mut settings = CompressionSettings::default();
+        settings.linked_blocks().content_checksum(false).block_checksums(true).block_size(64 * 1024)
+            .dictionary(42, b"dict");
+        assert_eq!(settings.to_params_str(), "LZ4f,block=64K,bcsum,dict-id=42");
+
+        for params in &["LZ4f,block=4M,indep,csum", "LZ4f,block=64K,bcsum,dict-id=42", "LZ4f,block=1M"] {
+            assert_eq!(&CompressionSettings::from_params_str(params).unwrap().to_params_str(), params);
+        }
+        let mut roundtrip = CompressionSettings::from_params_str(&settings.to_params_str()).unwrap();
+        assert_ne!(roundtrip, settings);
+        assert_eq!(roundtrip.dictionary(42, &b"dictionary"[..4]), &settings);
+        assert_eq!(CompressionSettings::from_params_str("LZ4f,block=262144").unwrap().to_params_str(), "LZ4f,block=256K");
+        assert_eq!(CompressionSettings::from_params_str("LZ4f,csum").unwrap().to_params_str(), "LZ4f,block=4M,csum");
+
+        assert_eq!(CompressionSettings::from_params_str("indep").err(), Some(ParamsParseError::MissingPrefix));
+        for &size in &["0", "1", "3", "65535", "18446744073709551615"] {
+            assert_eq!(CompressionSettings::from_params_str(&format!("LZ4f,block={}", size)).err(), Some(ParamsParseError::InvalidBlockSize(size.into())));
+        }
+        assert_eq!(CompressionSettings::from_params_str("LZ4f,block=2M").err(), Some(ParamsParseError::InvalidBlockSize("2M".into())));
+        assert_eq!(CompressionSettings::from_params_str("LZ4f,dict-id=x").err(), Some(ParamsParseError::InvalidDictionaryId("x".into())));
+        assert_eq!(CompressionSettings::from_params_str("LZ4f,fast").err(), Some(ParamsParseError::UnknownParameter("fast".into())));
+    }
+
+    #[test]
+    fn progress() {
+        let plaintext = plaintext();
+        let mut calls = Vec::new();
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024)
+            .compress_with_size_and_progress(std::io::Cursor::new(&plaintext), &mut compressed, |read, total| calls.push((read, total)))
+            .unwrap();
+
+        let total = plaintext.len() as u64;
+        let expected: Vec<_> = (1..=5).map(|i| (std::cmp::min(i * 64 * 1024, total), total)).collect();
+        assert_eq!(calls, expected);
+        assert_eq!(decompress_frame(compressed.as_slice()).unwrap(), plaintext);
+    }
+
+    /// A reader whose end is before its start, as with a file that got truncated concurrently.
+    struct ShrinkingReader(u64);
+    impl std::io::Read for ShrinkingReader {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> { Ok(0) }
+    }
+    impl std::io::Seek for ShrinkingReader {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            match pos {
+                std::io::SeekFrom::End(_) => Ok(0),
+                _ => Ok(self.0),
+            }
+        }
+    }
+
+    #[test]
+    fn size_of_shrinking_reader() {
+        let mut compressed = Vec::new();
+        let e = CompressionSettings::default().compress_with_size(ShrinkingReader(100), &mut compressed).unwrap_err();
+        assert!(matches!(e, crate::framed::CompressionError::ReadError(ref e) if e.kind() == std::io::ErrorKind::InvalidInput), "{:?}", e);
+        assert!(compressed.is_empty());
+    }
+
+    #[test]
+    fn count() {
+        let plaintext = plaintext();
+        for &checksums in &[false, true] {
+            let mut settings = CompressionSettings::default();
+            settings.block_size(64 * 1024).block_checksums(checksums).content_checksum(checksums).dictionary(3, &plaintext[..1000]);
+            let mut compressed = Vec::new();
+            let (read, written) = settings.compress_count(plaintext.as_slice(), &mut compressed).unwrap();
+            assert_eq!(read, plaintext.len() as u64);
+            assert_eq!(written, compressed.len() as u64);
+        }
+
+        let mut compressed = Vec::new();
+        assert_eq!(CompressionSettings::default().compress_count(&[][..], &mut compressed).unwrap(), (0, compressed.len() as u64));
+    }
+
+    #[test]
+    fn max_output_bytes() {
+        let plaintext = plaintext();
+        let mut compressed = Vec::new();
+        let (_, full_len) = CompressionSettings::default().block_size(64 * 1024).compress_count(plaintext.as_slice(), &mut compressed).unwrap();
+
+        let mut exact = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).max_output_bytes(full_len).compress(plaintext.as_slice(), &mut exact).unwrap();
+        assert_eq!(exact, compressed);
+
+        for &limit in &[0, 10, full_len / 2, full_len - 1] {
+            let mut partial = Vec::new();
+            let e = CompressionSettings::default().block_size(64 * 1024).max_output_bytes(limit)
+                .compress(plaintext.as_slice(), &mut partial).unwrap_err();
+            assert!(matches!(e, crate::framed::CompressionError::OutputLimitExceeded), "{:?}", e);
+            // everything that was written is a prefix of the full frame, ending at a block boundary
+            assert!(partial.len() as u64 <= limit);
+            assert_eq!(partial, compressed[..partial.len()]);
+        }
+    }
+
+    /// Accepts `capacity` bytes in total, then returns `Ok(0)` forever.
+    #[derive(Debug)]
+    struct FullWriter {
+        written: usize,
+        capacity: usize,
+    }
+    impl Write for FullWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.capacity - self.written);
+            self.written += len;
+            Ok(len)
+        }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn write_zero() {
+        let plaintext = plaintext();
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).block_checksums(true);
+        let mut compressed = Vec::new();
+        settings.compress(plaintext.as_slice(), &mut compressed).unwrap();
+        let is_write_zero = |e: &CompressionError| matches!(e, CompressionError::WriteError(e) if e.kind() == ErrorKind::WriteZero);
+        // the last one fits everything but the end mark and content checksum
+        for &capacity in &[0, 3, 6, 10, 1000, compressed.len() - 8] {
+            let writer = || FullWriter { written: 0, capacity };
+            let e = settings.compress(plaintext.as_slice(), writer()).unwrap_err();
+            assert!(is_write_zero(&e), "{:?}", e);
+            assert_eq!(std::io::Error::from(e).kind(), ErrorKind::WriteZero);
+            let e = settings.compress_parallel_blocks(plaintext.as_slice(), writer(), 2).unwrap_err();
+            assert!(is_write_zero(&e), "{:?}", e);
+
+            let e = settings.streaming_compress(writer()).and_then(|mut frame_writer| {
+                frame_writer.write_all(&plaintext)?;
+                frame_writer.finish()
+            }).unwrap_err();
+            assert!(is_write_zero(&e), "{:?}", e);
+        }
+
+        let e = decompress_frame_to_writer(compressed.as_slice(), FullWriter { written: 0, capacity: 1000 }).unwrap_err();
+        assert!(matches!(e, DecompressionError::OutputError(ref e) if e.kind() == ErrorKind::WriteZero), "{:?}", e);
+        assert_eq!(std::io::Error::from(e).kind(), ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn write_after_failure() {
+        let plaintext = plaintext();
+        let mut buffer = [0u8; 1000];
+        let mut writer = CompressionSettings::default().block_size(64 * 1024).streaming_compress(&mut buffer[..]).unwrap();
+        assert!(writer.write_all(&plaintext).is_err());
+        // the failed block must not be compressed again with a hash table that already contains it
+        assert!(writer.write_all(&plaintext).is_err());
+        assert!(writer.flush().is_err());
+    }
+
+    /// Returns `len` bytes of `plaintext()`, then fails.
+    struct FailingReader {
+        data: Vec<u8>,
+        pos: usize,
+        len: usize,
+    }
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.pos == self.len {
+                return Err(std::io::Error::other("read failed"));
+            }
+            let amt = buf.len().min(self.len - self.pos);
+            buf[..amt].copy_from_slice(&self.data[self.pos..][..amt]);
+            self.pos += amt;
+            Ok(amt)
+        }
+    }
+
+    #[test]
+    fn read_error_leaves_frame_unterminated() {
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024);
+        let reader = || FailingReader { data: plaintext(), pos: 0, len: 100_000 };
+        let is_read_error = |e: &CompressionError| matches!(e, CompressionError::ReadError(_));
+
+        let mut compressed = Vec::new();
+        let e = settings.compress(reader(), &mut compressed).unwrap_err();
+        assert!(is_read_error(&e), "{:?}", e);
+        assert!(decompress_frame(compressed.as_slice()).is_err());
+
+        let mut compressed = Vec::new();
+        let e = settings.compress_parallel_blocks(reader(), &mut compressed, 2).unwrap_err();
+        assert!(is_read_error(&e), "{:?}", e);
+        assert!(decompress_frame(compressed.as_slice()).is_err());
+    }
+
+    /// Collects what is logged on the current thread, so tests running in parallel don't interfere.
+    struct CaptureLogger;
+    thread_local! {
+        static LOGGED: std::cell::RefCell<Vec<(log::Level, String)>> = std::cell::RefCell::new(Vec::new());
+    }
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata<'_>) -> bool { true }
+        fn log(&self, record: &log::Record<'_>) {
+            LOGGED.with(|logged| logged.borrow_mut().push((record.level(), record.args().to_string())));
+        }
+        fn flush(&self) {}
+    }
+
+    fn logged_by<F: FnOnce()>(f: F) -> Vec<(log::Level, String)> {
+        static LOGGER: CaptureLogger = CaptureLogger;
+        // fails if another test already did this, which is fine
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Trace);
+        LOGGED.with(|logged| logged.borrow_mut().clear());
+        f();
+        LOGGED.with(|logged| logged.borrow_mut().split_off(0))
+    }
+
+    #[test]
+    fn warns_about_unchecked_linked_blocks() {
+        let compress = |settings: &mut CompressionSettings<'_>| logged_by(|| {
+            settings.compress(&b"linked"[..], Vec::new()).unwrap();
+        });
+        let logged = compress(CompressionSettings::default().linked_blocks().content_checksum(false));
+        assert_eq!(logged.len(), 1);
+        assert_eq!(logged[0].0, log::Level::Warn);
+        assert!(logged[0].1.contains("linked blocks but neither block nor content checksums"), "{}", logged[0].1);
+
+        assert!(compress(CompressionSettings::default().linked_blocks()).is_empty());
+        assert!(compress(CompressionSettings::default().linked_blocks().content_checksum(false).block_checksums(true)).is_empty());
+        assert!(compress(CompressionSettings::default().content_checksum(false)).is_empty());
+    }
+}
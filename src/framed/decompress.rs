@@ -1,14 +1,31 @@
-use byteorder::{LE, ReadBytesExt};
+use byteorder::{ByteOrder, LE, ReadBytesExt};
 use std::hash::Hasher;
-use std::io::{self, Read, BufRead, ErrorKind};
+use std::io::{self, Read, BufRead, Write, Seek, SeekFrom, ErrorKind};
 use std::cmp;
+use std::mem;
+use std::collections::{HashMap, VecDeque};
+#[cfg(feature = "parallel")]
+use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+#[cfg(feature = "parallel")]
+use std::sync::{mpsc, Mutex};
+#[cfg(feature = "parallel")]
+use std::thread;
+use std::fs::File;
+use std::path::Path;
 use std::convert::TryInto;
 use twox_hash::XxHash32;
+#[cfg(feature = "xxhash64")]
+use twox_hash::XxHash64;
 use thiserror::Error;
 use fehler::{throw, throws};
 
-use super::{MAGIC, INCOMPRESSIBLE, WINDOW_SIZE};
-use super::header::{self, Flags, BlockDescriptor};
+use super::{MAGIC, LEGACY_MAGIC, SKIPPABLE_MAGIC, SKIPPABLE_MAGIC_MASK, INCOMPRESSIBLE, WINDOW_SIZE};
+#[cfg(feature = "xxhash64")]
+use super::{XXH64_FRAME_MAGIC, XXH64_FRAME_TAG};
+use super::header::{self, Flags, BlockDescriptor, ReservedBits};
 use crate::raw;
 
 
@@ -17,55 +34,342 @@ use crate::raw;
 pub enum DecompressionError {
     #[error("error reading from the input you gave me")]
     InputError(#[from] io::Error),
+    #[error("error writing to the output you gave me")]
+    OutputError(io::Error),
     #[error("the raw LZ4 decompression failed (data corruption?)")]
     CodecError(#[from] raw::DecodeError),
     #[error("invalid header")]
     HeaderParseError(#[from] header::ParseError),
     #[error("wrong magic number in file header: {0:08x}")]
     WrongMagic(u32),
-    #[error("the header checksum was invalid")]
-    HeaderChecksumFail,
+    #[error("frame format version {0} is not supported, only version 1 is (this file needs a newer decoder)")]
+    UnsupportedVersion(u8),
+    /// `header_len` is the number of bytes covered by the checksum, i.e. everything between the magic number and the checksum.
+    #[error("the header checksum was invalid: the frame says {expected:#04x}, but the {header_len} header bytes hash to {actual:#04x}")]
+    HeaderChecksumFail { expected: u8, actual: u8, header_len: usize },
     #[error("a block checksum was invalid")]
     BlockChecksumFail,
     #[error("the frame checksum was invalid")]
     FrameChecksumFail,
+    #[cfg(feature = "xxhash64")]
+    #[error("the xxhash64 checksum following the frame was invalid")]
+    Xxh64ChecksumFail,
+    #[cfg(feature = "xxhash64")]
+    #[error("the frame is not followed by an xxhash64 checksum frame")]
+    Xxh64ChecksumMissing,
     #[error("stream contains a compressed block with a size so large we can't even compute it (let alone fit the block in memory...)")]
     BlockLengthOverflow,
     #[error("a block decompressed to more data than allowed")]
     BlockSizeOverflow,
+    #[error("the frame decompresses to more data than the limit allows")]
+    OutputLimitExceeded,
+    #[error("the frame contains more blocks than the limit allows")]
+    BlockCountLimitExceeded,
+    #[error("the frame declares a block size larger than the limit allows")]
+    BlockSizeLimitExceeded,
+    #[error("the dictionary must be provided before decoding any blocks")]
+    DictionaryAfterDecoding,
+    #[error("the frame needs dictionary {0}, but there is no such dictionary")]
+    DictionaryRequired(u32),
+    #[error("the frame needs dictionary {0}, but this function doesn't support dictionaries")]
+    DictionaryNotSupported(u32),
+    #[error("the frame header says the content is {expected} bytes, but it decompressed to {actual} bytes")]
+    ContentSizeMismatch { expected: u64, actual: u64 },
+    #[error("the frame has linked blocks, which can only be decoded one after the other")]
+    LinkedBlocks,
+    #[error("you must pass an empty buffer to decode_block")]
+    NonEmptyOutputBuffer,
 }
 type Error = DecompressionError; // do it this way for better docs
 
 impl From<Error> for io::Error {
     fn from(e: Error) -> io::Error {
-        io::Error::new(ErrorKind::Other, e)
+        // keep the kind of output errors so callers can still tell e.g. `WriteZero` apart
+        let kind = match &e {
+            Error::OutputError(e) => e.kind(),
+            _ => ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Resource limits for decoding untrusted frames.
+///
+/// A tiny frame can legally declare 4 MiB blocks or decompress to gigabytes of data,
+/// so these limits are checked *before* the corresponding allocations happen.
+///
+/// Create it using `Default::default()`, which does not limit anything.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecompressionLimits {
+    max_output: Option<u64>,
+    max_blocks: Option<u64>,
+    max_block_size: Option<usize>,
+}
+impl DecompressionLimits {
+    /// Limit the total number of bytes the frame may decompress to.
+    pub fn max_output(&mut self, v: u64) -> &mut Self {
+        self.max_output = Some(v);
+        self
+    }
+
+    /// Limit the number of blocks in the frame.
+    pub fn max_blocks(&mut self, v: u64) -> &mut Self {
+        self.max_blocks = Some(v);
+        self
+    }
+
+    /// Reject frames whose header declares a larger block size than this.
+    /// This bounds the size of the buffers the reader allocates.
+    pub fn max_block_size(&mut self, v: usize) -> &mut Self {
+        self.max_block_size = Some(v);
+        self
+    }
+}
+
+/// Everything that can be configured for reading a frame, the counterpart to `CompressionSettings`.
+///
+/// Create it using `Default::default()`, which behaves like the reference decoder.
+/// The constructors of `LZ4FrameReader` and the `decompress_frame*` functions are shorthands for the default settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecompressionSettings<'a> {
+    dictionary: Option<&'a [u8]>,
+    limits: DecompressionLimits,
+    verify_checksums: bool,
+    reserved_bits: ReservedBits,
+    unknown_block_size: Option<usize>,
+}
+impl<'a> Default for DecompressionSettings<'a> {
+    fn default() -> Self {
+        DecompressionSettings {
+            dictionary: None,
+            limits: DecompressionLimits::default(),
+            verify_checksums: true,
+            reserved_bits: ReservedBits::Strict,
+            unknown_block_size: None,
+        }
+    }
+}
+impl<'a> DecompressionSettings<'a> {
+    /// The dictionary the frame was compressed with (see `LZ4FrameReader::with_dictionary`).
+    ///
+    /// By default, no dictionary is used.
+    pub fn dictionary(&mut self, dict: &'a [u8]) -> &mut Self {
+        self.dictionary = Some(dict);
+        self
+    }
+
+    /// Enforce resource limits for decoding untrusted frames.
+    ///
+    /// By default, nothing is limited.
+    pub fn limits(&mut self, v: DecompressionLimits) -> &mut Self {
+        self.limits = v;
+        self
+    }
+
+    /// Whether block and content checksums are verified (if the frame has them).
+    ///
+    /// Skipping this saves the time spent hashing, but corrupted data goes undetected
+    /// (unless it happens to fail decoding). Checksums are verified by default.
+    pub fn verify_checksums(&mut self, v: bool) -> &mut Self {
+        self.verify_checksums = v;
+        self
+    }
+
+    /// Whether frames that set reserved header bits are rejected (`Strict`, the default) or accepted (`Lenient`).
+    pub fn reserved_bits(&mut self, v: ReservedBits) -> &mut Self {
+        self.reserved_bits = v;
+        self
+    }
+
+    /// Accept frames with a block size code outside of the ones the spec defines (some early encoders wrote
+    /// codes 0 to 3 for blocks smaller than 64 KiB) and treat their blocks as being at most `v` bytes.
+    ///
+    /// The limits still apply to `v`, just like to any other block size. By default, such frames are rejected.
+    pub fn unknown_block_size(&mut self, v: usize) -> &mut Self {
+        self.unknown_block_size = Some(v);
+        self
+    }
+
+    /// Create an `LZ4FrameReader` with these settings and parse the header.
+    #[throws]
+    pub fn frame_reader<R: Read>(&self, reader: R) -> LZ4FrameReader<R> {
+        LZ4FrameReader::new_with_settings(reader, *self)?
+    }
+
+    /// Decompress a frame and write the plaintext to `writer`, returning the number of bytes written.
+    ///
+    /// Every block goes to `writer.write_all` as soon as it is decoded, so a slow writer holds up decoding
+    /// (one block at a time) rather than the whole frame piling up in memory.
+    /// Errors from the writer are reported as `OutputError`.
+    #[throws]
+    pub fn decompress<R: Read, W: Write>(&self, reader: R, mut writer: W) -> u64 {
+        let mut frame_reader = self.frame_reader(reader)?;
+        let mut block = Vec::new();
+        loop {
+            block.clear();
+            if let BlockOutcome::EndOfFrame { .. } = frame_reader.decode_block_ex(&mut block, &[])? {
+                break;
+            }
+            writer.write_all(&block).map_err(Error::OutputError)?;
+        }
+        frame_reader.decompressed_bytes_written()
+    }
+
+    /// Like `decompress`, but writes the plaintext to a file, returning the number of bytes written.
+    ///
+    /// The file is created if it doesn't exist and truncated if it does. Before returning, the file is synced to disk,
+    /// so write errors that would otherwise only happen when it's closed (and go unnoticed) are reported as well.
+    /// On error, the file contains whatever was decoded up to that point.
+    #[throws]
+    pub fn decompress_to_file<R: Read, P: AsRef<Path>>(&self, reader: R, path: P) -> u64 {
+        let mut writer = io::BufWriter::new(File::create(path).map_err(Error::OutputError)?);
+        let bytes_written = self.decompress(reader, &mut writer)?;
+        let file = writer.into_inner().map_err(|e| Error::OutputError(e.into_error()))?;
+        file.sync_all().map_err(Error::OutputError)?;
+        bytes_written
+    }
+}
+
+/// A dictionary for decompression that is cheap to clone and can be shared between threads.
+///
+/// Load it once and hand clones to as many readers as you like (see `LZ4FrameReader::with_decoder_dictionary`),
+/// they all share the same bytes. Only the trailing 64 KiB are kept, as nothing before that can be referenced.
+/// `CompressionSettings::decoder_dictionary` turns the dictionary you compressed with into one of these.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DecoderDictionary {
+    id: u32,
+    data: Arc<[u8]>,
+}
+impl DecoderDictionary {
+    /// Copy the dictionary with this id (see `CompressionSettings::dictionary`).
+    pub fn new(id: u32, dict: &[u8]) -> Self {
+        DecoderDictionary { id, data: dict[dict.len().saturating_sub(WINDOW_SIZE)..].into() }
+    }
+
+    /// The dictionary id, as in `CompressionSettings::dictionary`.
+    pub fn id(&self) -> u32 { self.id }
+}
+impl AsRef<[u8]> for DecoderDictionary {
+    fn as_ref(&self) -> &[u8] { &self.data }
+}
+
+/// Looks up dictionaries by the id from the frame header, see `LZ4FrameReader::with_dictionary_provider`.
+pub trait DictionaryProvider {
+    /// The dictionary with this id, or `None` if there is no such dictionary.
+    fn get(&self, id: u32) -> Option<Cow<'_, [u8]>>;
+
+    /// Like `get`, but as a `DecoderDictionary`. Override this if you can hand out clones instead of copying.
+    fn get_shared(&self, id: u32) -> Option<DecoderDictionary> {
+        self.get(id).map(|dict| DecoderDictionary::new(id, &dict))
+    }
+}
+impl DictionaryProvider for DecoderDictionary {
+    fn get(&self, id: u32) -> Option<Cow<'_, [u8]>> {
+        self.get_shared(id).map(|_| Cow::Borrowed(self.as_ref()))
+    }
+    fn get_shared(&self, id: u32) -> Option<DecoderDictionary> {
+        Some(self.clone()).filter(|dict| dict.id == id)
+    }
+}
+impl DictionaryProvider for [DecoderDictionary] {
+    fn get(&self, id: u32) -> Option<Cow<'_, [u8]>> {
+        self.iter().find(|dict| dict.id == id).map(|dict| Cow::Borrowed(dict.as_ref()))
+    }
+    fn get_shared(&self, id: u32) -> Option<DecoderDictionary> {
+        self.iter().find(|dict| dict.id == id).cloned()
+    }
+}
+impl<D: AsRef<[u8]>, S: BuildHasher> DictionaryProvider for HashMap<u32, D, S> {
+    fn get(&self, id: u32) -> Option<Cow<'_, [u8]>> {
+        HashMap::get(self, &id).map(|dict| Cow::Borrowed(dict.as_ref()))
     }
 }
 
 /// Wrapper around `LZ4FrameReader` that implements `Read` and `BufRead`.
+///
+/// Through the blanket impls in std, `&mut LZ4FrameIoReader` implements both traits as well,
+/// so you can hand it to APIs that consume a reader and keep using it afterwards.
 pub struct LZ4FrameIoReader<'a, R: Read> {
     frame_reader: LZ4FrameReader<R>,
     bytes_taken: usize,
     buffer: Vec<u8>,
     dictionary: &'a [u8],
+    /// An error that occurred after `read` had already copied some data, to be returned by the next call.
+    deferred_error: Option<io::Error>,
+}
+impl<R: Read> LZ4FrameIoReader<'_, R> {
+    /// See `LZ4FrameReader::block_size`.
+    pub fn block_size(&self) -> Option<usize> { self.frame_reader.block_size() }
+    /// See `LZ4FrameReader::frame_size`.
+    pub fn frame_size(&self) -> Option<u64> { self.frame_reader.frame_size() }
+    /// See `LZ4FrameReader::remaining_blocks_hint`.
+    pub fn remaining_blocks_hint(&self) -> Option<u64> { self.frame_reader.remaining_blocks_hint() }
+    /// See `LZ4FrameReader::dictionary_id`.
+    pub fn dictionary_id(&self) -> Option<u32> { self.frame_reader.dictionary_id() }
+    /// See `LZ4FrameReader::compressed_bytes_read`.
+    pub fn compressed_bytes_read(&self) -> u64 { self.frame_reader.compressed_bytes_read() }
+    /// See `LZ4FrameReader::decompressed_bytes_written`.
+    ///
+    /// Note that this counts everything that was decompressed so far, including data that was buffered
+    /// but not yet returned by `read`.
+    pub fn decompressed_bytes_written(&self) -> u64 { self.frame_reader.decompressed_bytes_written() }
+    /// See `LZ4FrameReader::frame_info`.
+    pub fn frame_info(&self) -> Option<&FrameInfo> { self.frame_reader.frame_info() }
 }
 impl<R: Read> Read for LZ4FrameIoReader<'_, R> {
+    /// Fills `buf` completely unless the frame ends, decoding as many blocks as necessary.
     #[throws(io::Error)]
     fn read(&mut self, buf: &mut [u8]) -> usize {
-        let mybuf = self.fill_buf()?;
-        let bytes_to_take = cmp::min(mybuf.len(), buf.len());
-        &mut buf[..bytes_to_take].copy_from_slice(&mybuf[..bytes_to_take]);
-        self.consume(bytes_to_take);
-        bytes_to_take
+        let mut bytes_read = 0;
+        while bytes_read < buf.len() {
+            let mybuf = match self.fill_buf() {
+                Ok([]) => break,
+                Ok(mybuf) => mybuf,
+                Err(e) if bytes_read == 0 => throw!(e),
+                Err(e) => {
+                    // return what we have now and the error next time
+                    self.deferred_error = Some(e);
+                    break;
+                }
+            };
+            let bytes_to_take = cmp::min(mybuf.len(), buf.len() - bytes_read);
+            buf[bytes_read..][..bytes_to_take].copy_from_slice(&mybuf[..bytes_to_take]);
+            self.consume(bytes_to_take);
+            bytes_read += bytes_to_take;
+        }
+        bytes_read
+    }
+
+    /// Decodes the remaining blocks directly into `buf`, without going through our own buffer.
+    #[throws(io::Error)]
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        buf.extend_from_slice(&self.buffer[self.bytes_taken..]);
+        self.bytes_taken = self.buffer.len();
+        if let Some(e) = self.deferred_error.take() {
+            throw!(e);
+        }
+
+        self.frame_reader.decode_to_end(buf, self.dictionary)?;
+        buf.len() - start
     }
 }
 impl<R: Read> BufRead for LZ4FrameIoReader<'_, R> {
     #[throws(io::Error)]
     fn fill_buf(&mut self) -> &[u8] {
+        if let Some(e) = self.deferred_error.take() {
+            throw!(e);
+        }
         if self.bytes_taken == self.buffer.len() {
-            self.buffer.clear();
-            self.frame_reader.decode_block(&mut self.buffer, self.dictionary)?;
             self.bytes_taken = 0;
+            loop {
+                self.buffer.clear();
+                match self.frame_reader.decode_block_ex(&mut self.buffer, self.dictionary)? {
+                    // an empty block is not the end, keep going
+                    BlockOutcome::Data { len: 0, .. } => continue,
+                    _ => break,
+                }
+            }
         }
         &self.buffer[self.bytes_taken..]
     }
@@ -76,42 +380,153 @@ impl<R: Read> BufRead for LZ4FrameIoReader<'_, R> {
     }
 }
 
-/// Read an LZ4-compressed frame.
+/// A reader that decompresses its input if it is an LZ4 frame and passes it through unchanged otherwise.
 ///
-/// This reader reads the blocks inside a frame one by one.
-pub struct LZ4FrameReader<R: Read> {
-    reader: R,
+/// This looks at the first four bytes: if they are the LZ4 magic number, the frame is decoded.
+/// Skippable frames in front of it are skipped. Anything else is returned as is, including the peeked bytes.
+/// Only a single frame is decoded, just like with `LZ4FrameReader`.
+pub struct MaybeCompressedReader<R: Read> {
+    inner: MaybeCompressed<R>,
+}
+enum MaybeCompressed<R: Read> {
+    Compressed(Box<LZ4FrameIoReader<'static, io::Chain<io::Cursor<Vec<u8>>, R>>>),
+    Plain(io::Chain<io::Cursor<Vec<u8>>, R>),
+}
+
+impl<R: Read> MaybeCompressedReader<R> {
+    /// Peek at the start of `reader` and decide whether to decompress it.
+    ///
+    /// Legacy LZ4 frames are rejected rather than passed through, as are skippable frames that aren't followed by an LZ4 frame.
+    #[throws(io::Error)]
+    pub fn new(mut reader: R) -> Self {
+        let mut peeked = peek_magic(&mut reader)?;
+        let mut skipped = false;
+        while let Ok(magic) = peeked.as_slice().read_u32::<LE>() {
+            match magic {
+                MAGIC => {
+                    let frame_reader = LZ4FrameReader::new(io::Cursor::new(peeked).chain(reader))?;
+                    return MaybeCompressedReader { inner: MaybeCompressed::Compressed(Box::new(frame_reader.into_read())) };
+                }
+                LEGACY_MAGIC => throw!(io::Error::new(ErrorKind::InvalidData, "legacy LZ4 frames are not supported")),
+                m if m & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC => {
+                    let len = reader.read_u32::<LE>()?;
+                    if io::copy(&mut reader.by_ref().take(len.into()), &mut io::sink())? != u64::from(len) {
+                        throw!(io::Error::from(ErrorKind::UnexpectedEof));
+                    }
+                    peeked = peek_magic(&mut reader)?;
+                    skipped = true;
+                }
+                _ => break,
+            }
+        }
+        if skipped {
+            throw!(io::Error::new(ErrorKind::InvalidData, "skippable frame is not followed by an LZ4 frame"));
+        }
+        MaybeCompressedReader { inner: MaybeCompressed::Plain(io::Cursor::new(peeked).chain(reader)) }
+    }
+
+    /// Whether the input is being decompressed.
+    pub fn was_compressed(&self) -> bool {
+        matches!(self.inner, MaybeCompressed::Compressed(_))
+    }
+}
+impl<R: Read> Read for MaybeCompressedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            MaybeCompressed::Compressed(r) => r.read(buf),
+            MaybeCompressed::Plain(r) => r.read(buf),
+        }
+    }
+}
+
+/// Read up to four bytes, fewer only at the end of the input.
+#[throws(io::Error)]
+fn peek_magic<R: Read>(reader: R) -> Vec<u8> {
+    let mut peeked = Vec::with_capacity(4);
+    reader.take(4).read_to_end(&mut peeked)?;
+    peeked
+}
+
+/// The information stored in the header of an LZ4 frame.
+#[derive(Clone, Debug)]
+pub struct FrameInfo {
     flags: Flags,
     block_maxsize: usize,
-    read_buf: Vec<u8>,
     content_size: Option<u64>,
     dictionary_id: Option<u32>,
-    content_hasher: Option<XxHash32>,
-    carryover_window: Option<Vec<u8>>,
-    finished: bool,
 }
 
-impl<R: Read> LZ4FrameReader<R> {
-    /// Create a new LZ4FrameReader over an underlying reader and parse the header.
+impl FrameInfo {
+    /// Describe a frame with blocks of up to `block_size` bytes, which are linked and have no checksums.
     ///
-    /// A typical LZ4 file consists of exactly one frame.
-    /// This reader will stop reading at the end of the frame.
-    /// If you want to read any data following this frame, you should probably
-    /// pass in your reader by reference, rather than by value.
+    /// Use the setters to describe the rest of the frame, e.g. for `LZ4FrameReader::new_with_frame_info`
+    /// when the header is stored separately from the blocks.
+    pub fn new(block_size: usize) -> Self {
+        FrameInfo {
+            flags: Flags::empty(),
+            block_maxsize: block_size,
+            content_size: None,
+            dictionary_id: None,
+        }
+    }
+
+    pub fn set_content_size(&mut self, v: Option<u64>) -> &mut Self {
+        self.flags.set_content_size(v.is_some());
+        self.content_size = v;
+        self
+    }
+
+    pub fn set_dictionary_id(&mut self, v: Option<u32>) -> &mut Self {
+        self.flags.set_dictionary_id(v.is_some());
+        self.dictionary_id = v;
+        self
+    }
+
+    pub fn set_content_checksum(&mut self, v: bool) -> &mut Self {
+        self.flags.set_content_checksum(v);
+        self
+    }
+
+    pub fn set_block_checksums(&mut self, v: bool) -> &mut Self {
+        self.flags.set_block_checksums(v);
+        self
+    }
+
+    pub fn set_independent_blocks(&mut self, v: bool) -> &mut Self {
+        self.flags.set_independent_blocks(v);
+        self
+    }
+
+    /// Parse a frame header (starting with the magic number) without reading anything beyond it.
+    ///
+    /// This checks the header just like `LZ4FrameReader::new` does, so a corrupted header
+    /// fails with `HeaderChecksumFail`.
     #[throws]
-    pub fn new(mut reader: R) -> Self {
+    pub fn parse<R: Read>(mut reader: R) -> Self {
+        Self::read(&mut reader, ReservedBits::Strict, Checksums::Verify, None)?
+    }
+
+    #[throws]
+    fn read<R: Read>(reader: &mut R, reserved_bits: ReservedBits, checksums: Checksums, unknown_block_size: Option<usize>) -> Self {
         let magic = reader.read_u32::<LE>()?;
         if magic != MAGIC {
             throw!(Error::WrongMagic(magic));
         }
 
         let flags_byte = reader.read_u8()?;
-        let flags = Flags::parse(flags_byte)?;
-        let bd = BlockDescriptor::parse(reader.read_u8()?)?;
+        // check this first, as a different version could mean anything for the remaining bits
+        let version = flags_byte >> 6;
+        if version != 1 {
+            throw!(Error::UnsupportedVersion(version));
+        }
+        let flags = Flags::parse(flags_byte, reserved_bits)?;
+        let bd_byte = reader.read_u8()?;
+        let bd = BlockDescriptor::parse(bd_byte, reserved_bits)?;
 
+        // the checksum covers the bytes as they are, including any reserved bits we ignored
         let mut hasher = XxHash32::with_seed(0);
         hasher.write_u8(flags_byte);
-        hasher.write_u8(bd.0);
+        hasher.write_u8(bd_byte);
 
         let content_size = if flags.content_size() {
             let i = reader.read_u64::<LE>()?;
@@ -132,158 +547,3137 @@ impl<R: Read> LZ4FrameReader<R> {
         let header_checksum_desired = reader.read_u8()?;
         let header_checksum_actual = (hasher.finish() >> 8) as u8;
         if header_checksum_desired != header_checksum_actual {
-            throw!(Error::HeaderChecksumFail);
+            // the header checksum is so cheap that we check it even when skipping the others
+            warn_or_throw(checksums, Error::HeaderChecksumFail {
+                expected: header_checksum_desired,
+                actual: header_checksum_actual,
+                header_len: 2 + if flags.content_size() { 8 } else { 0 } + if flags.dictionary_id() { 4 } else { 0 },
+            })?;
         }
 
-        let content_hasher = if flags.content_checksum() {
-            Some(XxHash32::with_seed(0))
-        } else {
-            None
-        };
-
-        let carryover_window = if flags.independent_blocks() {
-            None
-        } else {
-            Some(Vec::with_capacity(WINDOW_SIZE))
-        };
-
-        LZ4FrameReader {
-            reader,
+        FrameInfo {
             flags,
-            block_maxsize: bd.block_maxsize()?,
+            block_maxsize: match (bd.block_maxsize(), unknown_block_size) {
+                (Err(header::ParseError::UnimplementedBlocksize(_)), Some(size)) => size,
+                // the block must fit into our address space
+                (size, _) => size?.try_into().or(Err(Error::BlockSizeOverflow))?,
+            },
             content_size,
             dictionary_id,
-            content_hasher,
-            carryover_window,
-            finished: false,
-            read_buf: Vec::new()
         }
     }
 
-    /// Returns the maximum number of bytes a block can decompress to (as specified by the file header).
-    ///
-    /// In general, all blocks in a frame except for the final one will have exactly this size.
-    /// (Although this is not strictly enforced and may be violated by hand-crafted inputs)
+    /// The size of the header in bytes (including magic and checksum).
+    fn header_len(&self) -> usize {
+        7 + if self.flags.content_size() { 8 } else { 0 } + if self.dictionary_id.is_some() { 4 } else { 0 }
+    }
+
+    /// Returns the maximum number of bytes a block can decompress to.
     pub fn block_size(&self) -> usize { self.block_maxsize }
-    /// Returns the number of bytes that this entire frame is supposed to decompress to.
-    /// This value is read directly from the file header and may be incorrect for malicious inputs.
+    /// Returns the number of bytes that the frame is supposed to decompress to, if specified.
     pub fn frame_size(&self) -> Option<u64> { self.content_size }
-    /// Return an identifier for the dictionary that was used to compress this frame.
-    ///
-    /// Dictionary identifiers are always application-specific. Note that the lz4 command line utility never
-    /// specifies a dictionary id, even if a dictionary was used.
+    /// Returns the application-specific dictionary identifier, if specified.
     pub fn dictionary_id(&self) -> Option<u32> { self.dictionary_id }
+    /// Returns whether the frame ends with a checksum over its entire content.
+    pub fn content_checksum(&self) -> bool { self.flags.content_checksum() }
+    /// Returns whether every block is followed by a checksum.
+    pub fn block_checksums(&self) -> bool { self.flags.block_checksums() }
+    /// Returns whether the blocks are independent of each other (see `CompressionSettings::unlinked_blocks`).
+    pub fn independent_blocks(&self) -> bool { self.flags.independent_blocks() }
+    /// Returns whether you can start decoding at any block rather than always having to decode the frame
+    /// from the beginning, which is the case for frames with independent blocks.
+    ///
+    /// A block index (the offset of each block) is all you need to make use of this.
+    pub fn supports_random_access(&self) -> bool { self.independent_blocks() }
+    /// Returns whether the frame specifies a dictionary id, i.e. needs a dictionary to be decompressed.
+    ///
+    /// Note that frames can also depend on a dictionary without saying so (the LZ4 CLI never writes dictionary ids),
+    /// see `CompressionSettings::dictionary_id_nonsense_override`.
+    pub fn requires_dictionary(&self) -> bool { self.dictionary_id.is_some() }
+}
 
-    /// Convert this `LZ4FrameReader` into something that implements `std::io::BufRead`.
+/// How much of a block we read before hashing it when verifying block checksums (comfortably fits into L1 cache).
+const CHECKSUM_CHUNK_SIZE: usize = 16 * 1024;
+
+/// What `LZ4FrameReader::decode_block_ex` found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockOutcome {
+    /// A block with `len` bytes of data. Stored blocks are the ones that were not compressed.
     ///
-    /// Note that `io::copy` has a small performance issue: https://github.com/rust-lang/rust/issues/49921
-    pub fn into_read_with_dictionary(self, dictionary: &[u8]) -> LZ4FrameIoReader<R> {
-        LZ4FrameIoReader {
-            buffer: Vec::with_capacity(self.block_size()),
-            bytes_taken: 0,
-            frame_reader: self,
-            dictionary,
-        }
-    }
+    /// Note that `len` can be zero: while pointless, empty blocks are perfectly legal.
+    Data { len: usize, stored: bool },
+    /// The end of the frame. If the frame has a content checksum, it has been verified.
+    EndOfFrame { checksum_verified: bool },
+}
 
-    /// Convenience wrapper in case you don't want to specify a dictionary.
-    pub fn into_read(self) -> LZ4FrameIoReader<'static, R> {
-        self.into_read_with_dictionary(&[])
+/// A block of a frame as it was stored, see `LZ4FrameReader::raw_blocks`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressedBlock {
+    /// The position of the block in its frame, starting at 0.
+    pub index: u64,
+    /// The block data, which is LZ4-compressed unless the block is stored.
+    pub data: Vec<u8>,
+    /// Stored blocks were not compressed because that wouldn't have saved anything.
+    pub stored: bool,
+    /// The block checksum, if the frame has block checksums.
+    pub checksum: Option<u32>,
+}
+
+/// Iterator over the blocks of a frame without decoding them, see `LZ4FrameReader::raw_blocks`.
+pub struct RawBlockIter<'a, R: Read> {
+    frame_reader: &'a mut LZ4FrameReader<R>,
+}
+impl<R: Read> Iterator for RawBlockIter<'_, R> {
+    type Item = Result<CompressedBlock, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.frame_reader.read_raw_block().transpose()
     }
+}
 
-    /// Decode a single block.
+/// Read an LZ4-compressed frame.
+///
+/// This reader reads the blocks inside a frame one by one.
+/// The underlying reader doesn't need to be a `BufRead`: short reads (down to a single byte per `read`)
+/// and `Interrupted` errors are handled, so sockets and pipes work as they are. We don't buffer anything
+/// beyond what we need either, so reading stops exactly at the end of the frame.
+pub struct LZ4FrameReader<R: Read> {
+    reader: R,
+    frame_info: Option<FrameInfo>,
+    read_buf: Vec<u8>,
+    content_hasher: Option<XxHash32>,
+    /// Only used for linked blocks.
+    carryover_window: Vec<u8>,
+    /// Set by `with_dictionary`, used whenever `decode_block` is not given a dictionary.
+    dictionary: Option<Arc<[u8]>>,
+    ignore_content_size: bool,
+    finished: bool,
+    /// Whether the content checksum was verified when the end mark was read, returned again by later calls.
+    checksum_verified: bool,
+    /// The length of the next block (or the end mark), if it was read along with the previous block.
+    next_block_length: Option<u32>,
+    compressed_bytes_read: u64,
+    decompressed_bytes_written: u64,
+    blocks_read: u64,
+    limits: DecompressionLimits,
+    checksums: Checksums,
+    /// Set by `skip_content_checksum`.
+    skip_content_checksum: bool,
+    /// Where the frame starts in the underlying reader, recorded by `new_seekable` for `restart`.
+    frame_start: Option<u64>,
+    reserved_bits: ReservedBits,
+    unknown_block_size: Option<usize>,
+}
+
+/// What an `LZ4FrameReader` does about checksums.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Checksums {
+    /// Fail on mismatches.
+    Verify,
+    /// Log mismatches and carry on (`new_lenient`).
+    Warn,
+    /// Don't even compute block and content checksums (`DecompressionSettings::verify_checksums`).
+    Skip,
+}
+
+impl<R: Read> LZ4FrameReader<R> {
+    /// Create a new LZ4FrameReader over an underlying reader and parse the header.
     ///
-    /// The `output` buffer must be empty upon calling this method.
+    /// A typical LZ4 file consists of exactly one frame.
+    /// This reader will stop reading at the end of the frame.
+    /// If you want to read any data following this frame, you should probably
+    /// pass in your reader by reference, rather than by value.
     #[throws]
-    pub fn decode_block(&mut self, output: &mut Vec<u8>, dictionary: &[u8]) {
-        assert!(output.is_empty(), "You must pass an empty buffer to this interface.");
-        
-        if self.finished { return; }
+    pub fn new(reader: R) -> Self {
+        let mut frame_reader = Self::new_lazy(reader);
+        frame_reader.read_header()?;
+        frame_reader
+    }
 
-        let reader = &mut self.reader;
+    /// Like `new`, but enforces the given resource limits.
+    #[throws]
+    pub fn with_limits(reader: R, limits: DecompressionLimits) -> Self {
+        let mut frame_reader = Self::new_lazy(reader);
+        frame_reader.limits = limits;
+        frame_reader.read_header()?;
+        frame_reader
+    }
 
-        let block_length = reader.read_u32::<LE>()?;
-        if block_length == 0 {
-            if let Some(hasher) = self.content_hasher.take() {
-                let checksum = reader.read_u32::<LE>()?;
-                if hasher.finish() != checksum.into() {
-                    throw!(Error::FrameChecksumFail);
-                }
-            }
-            self.finished = true;
-            return;
+    /// Like `new`, but with the given settings (see also `DecompressionSettings::frame_reader`).
+    #[throws]
+    pub fn new_with_settings(reader: R, settings: DecompressionSettings) -> Self {
+        let mut frame_reader = Self::new_lazy(reader);
+        frame_reader.limits = settings.limits;
+        if !settings.verify_checksums {
+            frame_reader.checksums = Checksums::Skip;
         }
+        frame_reader.reserved_bits = settings.reserved_bits;
+        frame_reader.unknown_block_size = settings.unknown_block_size;
+        if let Some(dict) = settings.dictionary {
+            frame_reader.set_dictionary(dict);
+        }
+        frame_reader.read_header()?;
+        frame_reader
+    }
 
-        let is_compressed = block_length & INCOMPRESSIBLE == 0;
-        let block_length = block_length & !INCOMPRESSIBLE;
+    /// Create a reader for a frame whose header you already parsed (e.g. using `FrameInfo::parse`)
+    /// or got from somewhere else (see `FrameInfo::new`). `reader` must be positioned at the first block.
+    ///
+    /// As the header is not read from `reader`, it doesn't count towards `compressed_bytes_read`.
+    #[throws]
+    pub fn new_with_frame_info(reader: R, info: FrameInfo) -> Self {
+        let mut frame_reader = Self::new_lazy(reader);
+        frame_reader.start_frame(info)?;
+        frame_reader
+    }
 
-        if block_length > self.block_maxsize as u32 {
-            throw!(Error::BlockSizeOverflow);
+    /// Like `new`, but uses the dictionary that `provider` has for the dictionary id in the header.
+    ///
+    /// Fails with `DictionaryRequired` if the provider doesn't know the id. Frames without a dictionary id
+    /// are decoded without a dictionary. Note that `reset` doesn't consult the provider again.
+    #[throws]
+    pub fn with_dictionary_provider<P: DictionaryProvider + ?Sized>(reader: R, provider: &P) -> Self {
+        let mut frame_reader = Self::new(reader)?;
+        if let Some(id) = frame_reader.dictionary_id() {
+            let dict = provider.get_shared(id).ok_or(Error::DictionaryRequired(id))?;
+            frame_reader.dictionary = Some(dict.data);
         }
+        frame_reader
+    }
 
-        let buf = &mut self.read_buf;
-        buf.resize(block_length.try_into().or(Err(Error::BlockLengthOverflow))?, 0);
-        reader.read_exact(buf.as_mut_slice())?;
+    /// Like `new`, but checksum mismatches (in the header, blocks or content) are logged as warnings instead of failing,
+    /// and reserved header bits are silently ignored (see `ReservedBits::Lenient`).
+    ///
+    /// This is for salvaging data from files written by buggy encoders. Be aware that the output
+    /// **may well be incorrect**: that's exactly what the checksums would have told you.
+    #[throws]
+    pub fn new_lenient(reader: R) -> Self {
+        let mut frame_reader = Self::new_lazy(reader);
+        frame_reader.checksums = Checksums::Warn;
+        frame_reader.reserved_bits = ReservedBits::Lenient;
+        frame_reader.read_header()?;
+        frame_reader
+    }
 
-        if self.flags.block_checksums() {
-            let checksum = reader.read_u32::<LE>()?;
-            let mut hasher = XxHash32::with_seed(0);
-            hasher.write(&buf);
-            if hasher.finish() != checksum.into() {
-                throw!(Error::BlockChecksumFail);
-            }
+    /// Create a new LZ4FrameReader without reading anything from the underlying reader yet.
+    ///
+    /// The header is parsed when you call `read_header` or decode the first block, whichever comes first.
+    /// This is useful if you need the reader object before any data is available (e.g. on a socket).
+    pub fn new_lazy(reader: R) -> Self {
+        LZ4FrameReader {
+            reader,
+            frame_info: None,
+            content_hasher: None,
+            carryover_window: Vec::new(),
+            dictionary: None,
+            ignore_content_size: false,
+            finished: false,
+            checksum_verified: false,
+            read_buf: Vec::new(),
+            next_block_length: None,
+            compressed_bytes_read: 0,
+            decompressed_bytes_written: 0,
+            blocks_read: 0,
+            limits: DecompressionLimits::default(),
+            checksums: Checksums::Verify,
+            skip_content_checksum: false,
+            frame_start: None,
+            reserved_bits: ReservedBits::Strict,
+            unknown_block_size: None,
         }
+    }
 
-        // set up the prefix properly
-        let dec_prefix = if let Some(window) = self.carryover_window.as_mut() {
-            if window.is_empty() {
-                window.extend_from_slice(dictionary);
-            }
-            window
-        } else {
-            dictionary
-        };
-        // decompress or copy, depending on whether this block is compressed
-        if is_compressed {
-            raw::decompress_raw(&buf, dec_prefix, output, self.block_maxsize)?;
-        } else {
-            output.extend_from_slice(&buf);
+    /// Parse the frame header unless that already happened.
+    ///
+    /// Readers created with `new` have already done this, so it's only useful for lazy readers.
+    #[throws]
+    pub fn read_header(&mut self) -> &FrameInfo {
+        if self.frame_info.is_none() {
+            let info = FrameInfo::read(&mut self.reader, self.reserved_bits, self.checksums, self.unknown_block_size)?;
+            self.compressed_bytes_read += info.header_len() as u64;
+            self.start_frame(info)?;
         }
-        // finally, push data back into the window as needed
-        if let Some(window) = self.carryover_window.as_mut() {
-            let outlen = output.len();
-            if outlen < WINDOW_SIZE {
-                let available_bytes = window.len() + outlen;
-                if let Some(surplus_bytes) = available_bytes.checked_sub(WINDOW_SIZE) {
-                    // remove as many bytes from front as we are replacing
-                    window.drain(..surplus_bytes);
-                }
-                window.extend_from_slice(&output);
-            } else {
-                // TODO: optimize this case to avoid the copy
-                window.clear();
-                window.extend_from_slice(&output[outlen - WINDOW_SIZE..]);
-            }
+        self.info()
+    }
 
-            assert!(window.len() <= WINDOW_SIZE);
+    /// Set up everything for decoding the blocks of a frame whose header has just been read.
+    #[throws]
+    fn start_frame(&mut self, mut info: FrameInfo) {
+        if self.ignore_content_size {
+            info.content_size = None;
+        }
+        if self.limits.max_block_size.is_some_and(|max| info.block_maxsize > max) {
+            throw!(Error::BlockSizeLimitExceeded);
         }
 
-
-        if output.len() > self.block_maxsize {
+        if info.flags.content_checksum() && self.checksums != Checksums::Skip && !self.skip_content_checksum {
+            self.content_hasher = Some(XxHash32::with_seed(0));
+        }
+        self.carryover_window.clear();
+        if !info.flags.independent_blocks() {
+            self.carryover_window.reserve(WINDOW_SIZE);
+        }
+        self.frame_info = Some(info);
+    }
+
+    /// Start reading a new frame from a different reader, keeping the internal buffers.
+    ///
+    /// This parses the new header right away, just like `new` does.
+    /// Decoding many small frames this way avoids most allocations.
+    #[throws]
+    pub fn reset(&mut self, new_reader: R) {
+        self.reader = new_reader;
+        self.frame_start = None;
+        self.rewind_state();
+        self.dictionary = None;
+        self.ignore_content_size = false;
+        self.read_header()?;
+    }
+
+    /// Forget everything about the current frame except for the user's choices.
+    fn rewind_state(&mut self) {
+        self.frame_info = None;
+        self.content_hasher = None;
+        self.finished = false;
+        self.checksum_verified = false;
+        self.next_block_length = None;
+        self.compressed_bytes_read = 0;
+        self.decompressed_bytes_written = 0;
+        self.blocks_read = 0;
+    }
+
+    /// Don't verify that the frame decompresses to the content size from the header.
+    ///
+    /// This is for recovering data from frames with a bogus content size. Afterwards, `frame_size` returns `None`.
+    /// By default, a frame that decompresses to a different size fails with `ContentSizeMismatch`.
+    pub fn ignore_content_size(&mut self) {
+        self.ignore_content_size = true;
+        if let Some(info) = self.frame_info.as_mut() {
+            info.content_size = None;
+        }
+    }
+
+    /// Don't compute the content checksum, just read and discard it at the end of the frame.
+    ///
+    /// This saves hashing every decompressed byte if you trust the data. Block checksums are still verified.
+    /// The checksum covers the entire content, so turning this back off only works before decoding any blocks.
+    /// Unlike `ignore_content_size`, this sticks across `reset`.
+    pub fn skip_content_checksum(&mut self, skip: bool) {
+        self.skip_content_checksum = skip;
+        if skip {
+            self.content_hasher = None;
+        } else if let Some(info) = self.frame_info.as_ref() {
+            if info.flags.content_checksum() && self.checksums != Checksums::Skip && self.blocks_read == 0 && !self.finished {
+                self.content_hasher = Some(XxHash32::with_seed(0));
+            }
+        }
+    }
+
+    /// Provide the dictionary for this frame, e.g. after looking it up by `dictionary_id`.
+    ///
+    /// The reader keeps its own copy (of the trailing 64 KiB, as nothing before that can be referenced)
+    /// and uses it whenever you don't pass a dictionary to `decode_block`, which includes `into_read`.
+    /// This must happen before decoding any blocks, otherwise it fails with `DictionaryAfterDecoding`.
+    /// `reset` forgets the dictionary.
+    #[throws]
+    pub fn with_dictionary(mut self, dict: &[u8]) -> Self {
+        if self.blocks_read != 0 || self.finished {
+            throw!(Error::DictionaryAfterDecoding);
+        }
+        self.set_dictionary(dict);
+        self
+    }
+
+    /// Like `with_dictionary`, but shares the dictionary instead of copying it.
+    ///
+    /// Fails with `DictionaryRequired` if the frame specifies a different dictionary id.
+    #[throws]
+    pub fn with_decoder_dictionary(mut self, dict: DecoderDictionary) -> Self {
+        if self.blocks_read != 0 || self.finished {
+            throw!(Error::DictionaryAfterDecoding);
+        }
+        if let Some(id) = self.dictionary_id().filter(|&id| id != dict.id) {
+            throw!(Error::DictionaryRequired(id));
+        }
+        self.dictionary = Some(dict.data);
+        self
+    }
+
+    fn set_dictionary(&mut self, dict: &[u8]) {
+        self.dictionary = Some(dict[dict.len().saturating_sub(WINDOW_SIZE)..].into());
+    }
+
+    /// Returns how many bytes of this frame have been read from the underlying reader so far.
+    ///
+    /// This counts everything: the header, block length prefixes, blocks and checksums.
+    pub fn compressed_bytes_read(&self) -> u64 { self.compressed_bytes_read }
+    /// Returns how many bytes this frame has decompressed to so far.
+    ///
+    /// Together with `frame_size` (if specified), this is useful for progress reporting.
+    pub fn decompressed_bytes_written(&self) -> u64 { self.decompressed_bytes_written }
+
+    /// Returns the parsed frame header, or `None` if a lazy reader hasn't read it yet.
+    pub fn frame_info(&self) -> Option<&FrameInfo> { self.frame_info.as_ref() }
+
+    fn info(&self) -> &FrameInfo {
+        self.frame_info.as_ref().expect("The frame header has not been read yet. Call read_header() first.")
+    }
+
+    /// Returns the maximum number of bytes a block can decompress to (as specified by the file header).
+    ///
+    /// In general, all blocks in a frame except for the final one will have exactly this size.
+    /// (Although this is not strictly enforced and may be violated by hand-crafted inputs)
+    ///
+    /// Returns `None` if this is a lazy reader that hasn't read the header yet.
+    pub fn block_size(&self) -> Option<usize> { Some(self.frame_info()?.block_maxsize) }
+    /// Returns the number of bytes that this entire frame is supposed to decompress to.
+    /// This value is read directly from the file header and may be incorrect for malicious inputs.
+    ///
+    /// Also returns `None` if this is a lazy reader that hasn't read the header yet.
+    pub fn frame_size(&self) -> Option<u64> { self.frame_info()?.content_size }
+    /// Returns roughly how many blocks are left to decode, if the header specifies the frame size.
+    ///
+    /// This is the remaining frame size divided by the block size (rounded up), so it's only an estimate:
+    /// it can be off if the encoder wrote blocks smaller than the block size (e.g. because it flushed early).
+    /// Good enough for a progress bar, but don't rely on it being exact.
+    pub fn remaining_blocks_hint(&self) -> Option<u64> {
+        let info = self.frame_info()?;
+        let remaining = info.content_size?.saturating_sub(self.decompressed_bytes_written);
+        Some(remaining.div_ceil(info.block_maxsize as u64))
+    }
+    /// Return an identifier for the dictionary that was used to compress this frame.
+    ///
+    /// Dictionary identifiers are always application-specific. Note that the lz4 command line utility never
+    /// specifies a dictionary id, even if a dictionary was used.
+    ///
+    /// Also returns `None` if this is a lazy reader that hasn't read the header yet.
+    pub fn dictionary_id(&self) -> Option<u32> { self.frame_info()?.dictionary_id }
+
+    /// Convert this `LZ4FrameReader` into something that implements `std::io::BufRead`.
+    ///
+    /// Note that `io::copy` has a small performance issue: https://github.com/rust-lang/rust/issues/49921
+    pub fn into_read_with_dictionary(self, dictionary: &[u8]) -> LZ4FrameIoReader<R> {
+        LZ4FrameIoReader {
+            buffer: Vec::with_capacity(self.frame_info().map_or(0, FrameInfo::block_size)),
+            bytes_taken: 0,
+            frame_reader: self,
+            dictionary,
+            deferred_error: None,
+        }
+    }
+
+    /// Convenience wrapper in case you don't want to specify a dictionary.
+    pub fn into_read(self) -> LZ4FrameIoReader<'static, R> {
+        self.into_read_with_dictionary(&[])
+    }
+
+    /// Decode a single block.
+    ///
+    /// The `output` buffer must be empty upon calling this method.
+    /// The output stays empty at the end of the frame (see `decode_block_ex` if you need to tell that apart from an empty block).
+    #[throws]
+    pub fn decode_block(&mut self, output: &mut Vec<u8>, dictionary: &[u8]) {
+        self.decode_block_ex(output, dictionary)?;
+    }
+
+    /// Decode a single block and report what was found.
+    ///
+    /// The `output` buffer must be empty upon calling this method, and it is left empty on error.
+    /// Otherwise, this fails with `NonEmptyOutputBuffer` (and panics in debug builds) without touching it.
+    /// Once the end of the frame has been reached, this keeps returning `EndOfFrame`.
+    #[throws]
+    pub fn decode_block_ex(&mut self, output: &mut Vec<u8>, dictionary: &[u8]) -> BlockOutcome {
+        debug_assert!(output.is_empty(), "You must pass an empty buffer to this interface.");
+        if !output.is_empty() {
+            throw!(Error::NonEmptyOutputBuffer);
+        }
+        match self.decode_block_into(output, dictionary) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                output.clear();
+                throw!(e);
+            }
+        }
+    }
+
+    /// Iterate over the remaining blocks of the frame as they were stored, without decoding them.
+    ///
+    /// This is for tools that copy, hash or re-frame compressed blocks. The stream is consumed just like
+    /// decoding would, so once the iterator ends, the underlying reader is right behind the frame.
+    /// Block checksums are verified, the content checksum and content size can't be.
+    /// Don't switch to decoding halfway through a frame with linked blocks, as the skipped blocks are missing from the window.
+    pub fn raw_blocks(&mut self) -> RawBlockIter<'_, R> {
+        RawBlockIter { frame_reader: self }
+    }
+
+    /// The step behind `raw_blocks`: read the next block or the end of the frame.
+    #[throws]
+    fn read_raw_block(&mut self) -> Option<CompressedBlock> {
+        if self.finished {
+            return None;
+        }
+        let info = self.read_header()?;
+        let flags = info.flags;
+        let block_maxsize = info.block_maxsize;
+        // the content checksum can't be verified without decoding
+        self.content_hasher = None;
+
+        let block_length = match self.next_block_length.take() {
+            Some(length) => length,
+            None => {
+                let length = self.reader.read_u32::<LE>()?;
+                self.compressed_bytes_read += 4;
+                length
+            }
+        };
+        if block_length == 0 {
+            if flags.content_checksum() {
+                self.reader.read_u32::<LE>()?;
+                self.compressed_bytes_read += 4;
+            }
+            self.finished = true;
+            return None;
+        }
+
+        let stored = block_length & INCOMPRESSIBLE != 0;
+        let block_length = block_length & !INCOMPRESSIBLE;
+        if block_length > block_maxsize as u32 {
+            throw!(Error::BlockSizeOverflow);
+        }
+        let blocks_read = self.count_block()?;
+
+        let block_length: usize = block_length.try_into().or(Err(Error::BlockLengthOverflow))?;
+        let mut data = vec![0; block_length + if flags.block_checksums() { 4 } else { 0 } + 4];
+        let (next_block_length, bytes_read) = read_block_data(&mut self.reader, &mut data, flags.block_checksums(), self.checksums)?;
+        self.next_block_length = next_block_length;
+        self.compressed_bytes_read += bytes_read as u64;
+        let checksum = if flags.block_checksums() { Some(LE::read_u32(&data[block_length..])) } else { None };
+        data.truncate(block_length);
+        Some(CompressedBlock { index: blocks_read - 1, data, stored, checksum })
+    }
+
+    /// Count another block of the frame against the limits, returning how many there have been so far.
+    #[throws]
+    fn count_block(&mut self) -> u64 {
+        self.blocks_read += 1;
+        if self.limits.max_blocks.is_some_and(|max| self.blocks_read > max) {
+            throw!(Error::BlockCountLimitExceeded);
+        }
+        self.blocks_read
+    }
+
+    /// Decode all remaining blocks, appending them to `output`.
+    ///
+    /// If the header declares the content size, that much space is reserved up front (within the limits).
+    /// On error, `output` keeps the blocks before the one that failed.
+    #[throws]
+    fn decode_to_end(&mut self, output: &mut Vec<u8>, dictionary: &[u8]) {
+        if let Some(size) = self.read_header()?.content_size {
+            // the header could be lying, so don't insist (and don't go beyond the limit either)
+            let size = cmp::min(size, self.limits.max_output.unwrap_or(u64::MAX));
+            let remaining = size.saturating_sub(self.decompressed_bytes_written);
+            let _ = output.try_reserve(remaining.try_into().unwrap_or(usize::MAX));
+        }
+        self.decode_blocks(output, dictionary, usize::MAX)?;
+    }
+
+    /// Decode blocks, appending them to `output`, until at least `max_bytes` have been appended or the frame ends.
+    ///
+    /// This is for previewing the start of a frame without decoding all of it. As only whole blocks are decoded,
+    /// this usually appends more than `max_bytes`. Afterwards, you can decode the rest of the frame as usual or simply
+    /// drop the reader (but then the content checksum is never verified). On error, `output` keeps the blocks before the one that failed.
+    #[throws]
+    pub fn decode_prefix(&mut self, max_bytes: usize, output: &mut Vec<u8>) {
+        self.decode_blocks(output, &[], max_bytes)?;
+    }
+
+    /// The loop behind `decode_to_end` and `decode_prefix`.
+    #[throws]
+    fn decode_blocks(&mut self, output: &mut Vec<u8>, dictionary: &[u8], max_bytes: usize) {
+        let start = output.len();
+        while output.len() - start < max_bytes {
+            let block_start = output.len();
+            match self.decode_block_into(output, dictionary) {
+                Ok(BlockOutcome::EndOfFrame { .. }) => break,
+                Ok(BlockOutcome::Data { .. }) => (),
+                Err(e) => {
+                    // don't return any part of a block that failed to decode
+                    output.truncate(block_start);
+                    throw!(e);
+                }
+            }
+        }
+    }
+
+    /// Like `decode_block_ex`, but appends the block to `output`.
+    ///
+    /// On error, `output` may contain some garbage after its original contents.
+    #[throws]
+    fn decode_block_into(&mut self, output: &mut Vec<u8>, dictionary: &[u8]) -> BlockOutcome {
+        let block_start = output.len();
+        if self.finished {
+            return BlockOutcome::EndOfFrame { checksum_verified: self.checksum_verified };
+        }
+
+        let info = self.read_header()?;
+        let flags = info.flags;
+        let block_maxsize = info.block_maxsize;
+        let content_size = info.content_size;
+
+        let block_length = match self.next_block_length.take() {
+            Some(length) => length,
+            None => {
+                let length = self.reader.read_u32::<LE>()?;
+                self.compressed_bytes_read += 4;
+                length
+            }
+        };
+        if block_length == 0 {
+            let mut checksum_verified = self.content_hasher.is_some();
+            if flags.content_checksum() {
+                let checksum = self.reader.read_u32::<LE>()?;
+                self.compressed_bytes_read += 4;
+                if let Some(hasher) = self.content_hasher.take() {
+                    if hasher.finish() != checksum.into() {
+                        checksum_verified = false;
+                        warn_or_throw(self.checksums, Error::FrameChecksumFail)?;
+                    }
+                }
+            }
+            if let Some(expected) = content_size.filter(|&size| size != self.decompressed_bytes_written) {
+                throw!(Error::ContentSizeMismatch { expected, actual: self.decompressed_bytes_written });
+            }
+            self.finished = true;
+            self.checksum_verified = checksum_verified;
+            return BlockOutcome::EndOfFrame { checksum_verified };
+        }
+
+        let is_compressed = block_length & INCOMPRESSIBLE == 0;
+        let block_length = block_length & !INCOMPRESSIBLE;
+
+        if block_length > block_maxsize as u32 {
+            throw!(Error::BlockSizeOverflow);
+        }
+
+        self.count_block()?;
+        // how much more we may decompress before exceeding either limit
+        let decompressed_bytes_written = self.decompressed_bytes_written;
+        let remaining_output = self.limits.max_output.map(|max| max.saturating_sub(decompressed_bytes_written));
+        let output_limit = match remaining_output {
+            Some(remaining) if remaining < block_maxsize as u64 => {
+                if !is_compressed && u64::from(block_length) > remaining {
+                    throw!(Error::OutputLimitExceeded);
+                }
+                remaining as usize
+            }
+            _ => block_maxsize,
+        };
+
+        let block_length: usize = block_length.try_into().or(Err(Error::BlockLengthOverflow))?;
+        let read_len = block_length + if flags.block_checksums() { 4 } else { 0 } + 4;
+        let (next_block_length, bytes_read) = if is_compressed {
+            // only zero-fill the buffer when it has to grow, rather than before every block
+            if self.read_buf.len() < read_len {
+                self.read_buf.resize(read_len, 0);
+            }
+            read_block_data(&mut self.reader, &mut self.read_buf[..read_len], flags.block_checksums(), self.checksums)?
+        } else {
+            // stored blocks go straight into the output, no need to copy them around
+            output.resize(block_start + read_len, 0);
+            let result = read_block_data(&mut self.reader, &mut output[block_start..], flags.block_checksums(), self.checksums);
+            output.truncate(block_start + block_length);
+            result?
+        };
+        self.next_block_length = next_block_length;
+        self.compressed_bytes_read += bytes_read as u64;
+
+        // set up the prefix properly
+        // the prefix is all a block may reference before its own start (the raw decoder rejects anything further back),
+        // so blocks of a frame that claims to be independent but isn't fail here rather than decoding garbage
+        let linked_blocks = !flags.independent_blocks();
+        let dictionary = if dictionary.is_empty() { self.dictionary.as_deref().unwrap_or_default() } else { dictionary };
+        let dec_prefix = if linked_blocks {
+            let window = &mut self.carryover_window;
+            if window.is_empty() {
+                window.extend_from_slice(dictionary);
+            }
+            window
+        } else {
+            dictionary
+        };
+        // decompress or copy, depending on whether this block is compressed
+        if is_compressed {
+            match raw::decompress_raw_append(&self.read_buf[..block_length], dec_prefix, output, output_limit) {
+                Err(raw::DecodeError::MemoryLimitExceeded { .. }) if output_limit < block_maxsize => throw!(Error::OutputLimitExceeded),
+                x => x?,
+            }
+        }
+        let block = &output[block_start..];
+        // finally, push data back into the window as needed
+        if linked_blocks {
+            let window = &mut self.carryover_window;
+            let outlen = block.len();
+            if outlen < WINDOW_SIZE {
+                let available_bytes = window.len() + outlen;
+                if let Some(surplus_bytes) = available_bytes.checked_sub(WINDOW_SIZE) {
+                    // remove as many bytes from front as we are replacing
+                    window.drain(..surplus_bytes);
+                }
+                window.extend_from_slice(block);
+            } else {
+                // TODO: optimize this case to avoid the copy
+                window.clear();
+                window.extend_from_slice(&block[outlen - WINDOW_SIZE..]);
+            }
+
+            assert!(window.len() <= WINDOW_SIZE);
+        }
+
+
+        if block.len() > block_maxsize {
             throw!(Error::BlockSizeOverflow);
         }
+        if block.len() > output_limit {
+            throw!(Error::OutputLimitExceeded);
+        }
+        let actual = self.decompressed_bytes_written + block.len() as u64;
+        if let Some(expected) = content_size.filter(|&size| actual > size) {
+            throw!(Error::ContentSizeMismatch { expected, actual });
+        }
 
         if let Some(hasher) = self.content_hasher.as_mut() {
-            hasher.write(&output);
+            hasher.write(block);
         }
+        self.decompressed_bytes_written += block.len() as u64;
+        BlockOutcome::Data { len: block.len(), stored: !is_compressed }
     }
 }
 
-/// Convenience wrapper around `LZ4FrameReader` that reads everything into a vector and returns it.
+/// Read the data of a block into `buf`, followed by the block checksum if the frame has them
+/// and the length of the next block (or the end mark), which takes up the last four bytes of `buf`.
+///
+/// A block is always followed by another length, so reading it right away never reads past the frame,
+/// but it saves a tiny read (i.e. a syscall on unbuffered readers) per block, two with block checksums.
+/// Returns the next length, or `None` if the input ended before it, along with the number of bytes read.
 #[throws]
-pub fn decompress_frame<R: Read>(reader: R) -> Vec<u8> {
-    let mut plaintext = Vec::new();
-    LZ4FrameReader::new(reader)?.into_read().read_to_end(&mut plaintext)?;
-    plaintext
+fn read_block_data<R: Read>(reader: &mut R, buf: &mut [u8], block_checksums: bool, checksums: Checksums) -> (Option<u32>, usize) {
+    let data_len = buf.len() - if block_checksums { 8 } else { 4 };
+    let mut hasher = if block_checksums && checksums != Checksums::Skip { Some(XxHash32::with_seed(0)) } else { None };
+    // hash each chunk right after reading it, while it's still in the cache,
+    // rather than making a second pass over the entire block afterwards
+    let chunk_size = if hasher.is_some() { CHECKSUM_CHUNK_SIZE } else { buf.len() };
+    let mut bytes_read = 0;
+    while bytes_read < buf.len() {
+        // the last chunk takes the trailing fields along
+        let chunk_end = if data_len.saturating_sub(bytes_read) > chunk_size { bytes_read + chunk_size } else { buf.len() };
+        let chunk = &mut buf[bytes_read..chunk_end];
+        let len = read_up_to(reader, chunk)?;
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.write(&chunk[..cmp::min(len, data_len.saturating_sub(bytes_read))]);
+        }
+        bytes_read += len;
+        if len < chunk.len() {
+            break;
+        }
+    }
+
+    let next_length_start = buf.len() - 4;
+    if bytes_read < next_length_start {
+        throw!(io::Error::from(ErrorKind::UnexpectedEof));
+    }
+    if let Some(hasher) = hasher {
+        if hasher.finish() != LE::read_u32(&buf[data_len..]).into() {
+            warn_or_throw(checksums, Error::BlockChecksumFail)?;
+        }
+    }
+    // if the next length is incomplete, the next read fails just like it would have without us
+    let next_length = Some(LE::read_u32(&buf[next_length_start..])).filter(|_| bytes_read == buf.len());
+    (next_length, bytes_read)
 }
 
+/// Like `read_exact`, but returns how many bytes were read instead of failing at the end of the input.
+#[throws(io::Error)]
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => throw!(e),
+        }
+    }
+    len
+}
+
+/// Fail with a checksum error, unless we are lenient about those.
+#[throws]
+fn warn_or_throw(checksums: Checksums, e: Error) {
+    if checksums == Checksums::Warn {
+        log::warn!("{}, continuing anyway", e);
+    } else {
+        throw!(e);
+    }
+}
+
+impl<R: Read + Seek> LZ4FrameReader<R> {
+    /// Like `new`, but also records where the frame starts, so that `restart` can go back there.
+    #[throws]
+    pub fn new_seekable(mut reader: R) -> Self {
+        let frame_start = reader.stream_position()?;
+        let mut frame_reader = Self::new(reader)?;
+        frame_reader.frame_start = Some(frame_start);
+        frame_reader
+    }
+
+    /// Seek back to the start of the frame and parse its header again, so the frame can be decoded once more.
+    ///
+    /// Unlike `reset`, this keeps the dictionary and `ignore_content_size`, as it's still the same frame.
+    /// Only readers created with `new_seekable` know where the frame starts, others fail with `Unsupported`.
+    #[throws(io::Error)]
+    pub fn restart(&mut self) {
+        let frame_start = self.frame_start.ok_or_else(|| io::Error::new(ErrorKind::Unsupported, "restart needs a reader created with new_seekable"))?;
+        self.reader.seek(SeekFrom::Start(frame_start))?;
+        self.rewind_state();
+        self.read_header()?;
+    }
+}
+
+/// Push-based decompression: write compressed data into it and the plaintext comes out into the inner writer.
+///
+/// This is the inverse of `LZ4FrameIoReader`. You can write the frame in arbitrarily small pieces;
+/// partial headers and blocks are buffered until they are complete, so at most one block is buffered at a time.
+/// Once the frame is complete, it does not accept any more data (`write` returns 0).
+/// Call `finish` to make sure the frame actually ended.
+///
+/// Note that after an error (including a failed write to the inner writer), the stream can not be resumed.
+pub struct LZ4FrameDecompressor<W: Write> {
+    frame_reader: LZ4FrameReader<VecDeque<u8>>,
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> LZ4FrameDecompressor<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_limits(writer, DecompressionLimits::default())
+    }
+
+    /// Like `new`, but enforces the given resource limits.
+    pub fn with_limits(writer: W, limits: DecompressionLimits) -> Self {
+        let mut frame_reader = LZ4FrameReader::new_lazy(VecDeque::new());
+        frame_reader.limits = limits;
+        LZ4FrameDecompressor { frame_reader, writer, buffer: Vec::new() }
+    }
+
+    /// The information from the frame header, once it has been written.
+    pub fn frame_info(&self) -> Option<&FrameInfo> { self.frame_reader.frame_info() }
+
+    /// Whether the entire frame has been decoded.
+    pub fn is_finished(&self) -> bool { self.frame_reader.finished }
+
+    /// Make sure the frame is complete and return the inner writer.
+    ///
+    /// Fails with an `UnexpectedEof` input error if the frame was cut short.
+    #[throws]
+    pub fn finish(self) -> W {
+        if !self.frame_reader.finished {
+            throw!(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        self.writer
+    }
+
+    /// Decode the header or block that has been buffered completely into `self.buffer`.
+    #[throws]
+    fn decode_unit(&mut self) {
+        self.buffer.clear();
+        if self.frame_reader.frame_info().is_none() {
+            self.frame_reader.read_header()?;
+        } else {
+            self.frame_reader.decode_block_ex(&mut self.buffer, &[])?;
+        }
+    }
+}
+
+impl<W: Write> Write for LZ4FrameDecompressor<W> {
+    #[throws(io::Error)]
+    fn write(&mut self, buf: &[u8]) -> usize {
+        let mut consumed = 0;
+        // only ever take as much as the next header or block needs, so we never swallow data after the frame
+        while consumed < buf.len() && !self.frame_reader.finished {
+            let wanted = match self.frame_reader.next_unit_len() { Ok(n) | Err(n) => n };
+            let amt = cmp::min(wanted - self.frame_reader.reader.len(), buf.len() - consumed);
+            self.frame_reader.reader.extend(&buf[consumed..][..amt]);
+            consumed += amt;
+
+            // units can be empty (e.g. an end mark that has been read along with the last block)
+            while !self.frame_reader.finished && matches!(self.frame_reader.next_unit_len(), Ok(n) if self.frame_reader.reader.len() >= n) {
+                self.decode_unit()?;
+                self.writer.write_all(&self.buffer)?;
+            }
+        }
+        consumed
+    }
+
+    /// Flushes the inner writer. Incomplete blocks stay buffered as they can't be decoded yet.
+    #[throws(io::Error)]
+    fn flush(&mut self) {
+        self.writer.flush()?;
+    }
+}
+
+impl LZ4FrameReader<VecDeque<u8>> {
+    /// How many bytes the next header or block takes up in total (`Ok`), or, if we can't tell yet,
+    /// how many bytes we need to find out (`Err`).
+    fn next_unit_len(&self) -> Result<usize, usize> {
+        let queued = &self.reader;
+        match self.frame_info() {
+            None => {
+                let flags = Flags::from_bits_truncate(*queued.get(4).ok_or(5usize)?);
+                Ok(7 + if flags.content_size() { 8 } else { 0 }
+                    + if flags.dictionary_id() { 4 } else { 0 })
+            }
+            Some(info) => {
+                // usually, the length has already been read along with the previous block
+                let (length_len, block_length) = match self.next_block_length {
+                    Some(length) => (0, length),
+                    None if queued.len() < 4 => return Err(4),
+                    None => (4, u32::from_le_bytes([queued[0], queued[1], queued[2], queued[3]])),
+                };
+                if block_length == 0 {
+                    return Ok(length_len + if info.content_checksum() { 4 } else { 0 });
+                }
+                let body_length = (block_length & !INCOMPRESSIBLE) as usize;
+                if body_length > info.block_maxsize {
+                    // decode_block rejects this before it even tries to read the body
+                    return Ok(length_len);
+                }
+                // decode_block reads the length of the following block too
+                Ok(length_len + body_length + if info.block_checksums() { 4 } else { 0 } + 4)
+            }
+        }
+    }
+}
+
+/// Decodes a frame that is still being written, e.g. a log file that a producer keeps appending to.
+///
+/// Rather than failing at the end of the input, `decode_block` returns `None` until the next block is complete,
+/// so you can wait for the file to grow and try again. It never reads past the header or block it's waiting for,
+/// so once the frame has ended, `next_frame` continues with the frame that follows (if any), like `tail -f`.
+pub struct LZ4FrameFollower<R: Read> {
+    reader: R,
+    /// Holds the header or block we are waiting for.
+    frame_reader: LZ4FrameReader<VecDeque<u8>>,
+    read_buf: Vec<u8>,
+}
+
+impl<R: Read> LZ4FrameFollower<R> {
+    pub fn new(reader: R) -> Self {
+        LZ4FrameFollower { reader, frame_reader: LZ4FrameReader::new_lazy(VecDeque::new()), read_buf: Vec::new() }
+    }
+
+    /// Decode the next block and append it to `output`, or return `None` if it isn't completely available yet.
+    /// On error, `output` is left as it was.
+    ///
+    /// The input not being available includes the reader returning a `WouldBlock` error, so this works with
+    /// non-blocking readers too. After the end of the frame, this keeps returning `EndOfFrame`.
+    #[throws]
+    pub fn decode_block(&mut self, output: &mut Vec<u8>) -> Option<BlockOutcome> {
+        loop {
+            let needed = match self.frame_reader.next_unit_len() {
+                _ if self.frame_reader.finished => 0,
+                Ok(n) => n.saturating_sub(self.frame_reader.reader.len()),
+                Err(n) => n - self.frame_reader.reader.len(),
+            };
+            if needed == 0 {
+                if self.frame_reader.frame_info().is_none() {
+                    self.frame_reader.read_header()?;
+                    continue;
+                }
+                let block_start = output.len();
+                let result = self.frame_reader.decode_block_into(output, &[]);
+                if result.is_err() {
+                    output.truncate(block_start);
+                }
+                break Some(result?);
+            }
+
+            self.read_buf.clear();
+            let result = (&mut self.reader).take(needed as u64).read_to_end(&mut self.read_buf);
+            // even on errors, read_to_end keeps what it read
+            self.frame_reader.reader.extend(&self.read_buf);
+            match result {
+                Ok(len) if len < needed => break None,
+                Ok(_) => (),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break None,
+                Err(e) => throw!(e),
+            }
+        }
+    }
+
+    /// The information from the frame header, once it is available.
+    pub fn frame_info(&self) -> Option<&FrameInfo> { self.frame_reader.frame_info() }
+
+    /// Whether the entire frame has been decoded.
+    pub fn is_finished(&self) -> bool { self.frame_reader.finished }
+
+    /// Start decoding the frame that follows this one.
+    ///
+    /// Panics if this frame hasn't ended yet.
+    pub fn next_frame(&mut self) {
+        assert!(self.frame_reader.finished, "the current frame hasn't ended yet");
+        self.frame_reader = LZ4FrameReader::new_lazy(mem::take(&mut self.frame_reader.reader));
+    }
+
+    /// Return the underlying reader, which is positioned right after the header or block we were waiting for.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Reusable buffers for decompressing many frames one after the other.
+///
+/// `decompress_frame` allocates its buffers for every frame, which makes up most of the work for tiny frames.
+/// Unlike `LZ4FrameReader::reset`, this works with a different type of reader for every frame.
+#[derive(Debug, Default)]
+pub struct Decompressor {
+    read_buf: Vec<u8>,
+    carryover_window: Vec<u8>,
+}
+
+impl Decompressor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decompress a frame, appending the plaintext to `output`, and return the information from its header.
+    ///
+    /// On error, `output` keeps the blocks before the one that failed.
+    #[throws]
+    pub fn decompress_into<R: Read>(&mut self, reader: R, output: &mut Vec<u8>) -> FrameInfo {
+        let mut frame_reader = LZ4FrameReader::new_lazy(reader);
+        frame_reader.read_buf = mem::take(&mut self.read_buf);
+        frame_reader.carryover_window = mem::take(&mut self.carryover_window);
+        let result = frame_reader.read_header().cloned()
+            .and_then(|info| frame_reader.decode_to_end(output, &[]).map(|()| info));
+        self.read_buf = frame_reader.read_buf;
+        self.carryover_window = frame_reader.carryover_window;
+        result?
+    }
+}
+
+/// Convenience wrapper around `LZ4FrameReader` that reads everything into a vector and returns it.
+#[throws]
+pub fn decompress_frame<R: Read>(reader: R) -> Vec<u8> {
+    decompress_frame_full(reader)?.0
+}
+
+/// Like `decompress_frame`, but enforces the given resource limits.
+#[throws]
+pub fn decompress_frame_with_limits<R: Read>(reader: R, limits: DecompressionLimits) -> Vec<u8> {
+    let mut plaintext = Vec::new();
+    LZ4FrameReader::with_limits(reader, limits)?.into_read().read_to_end(&mut plaintext)?;
+    plaintext
+}
+
+/// Like `decompress_frame`, but also returns the information from the frame header.
+///
+/// If this succeeds, all checksums that the frame has (see `FrameInfo`) have been verified.
+#[throws]
+pub fn decompress_frame_full<R: Read>(mut reader: R) -> (Vec<u8>, FrameInfo) {
+    let mut plaintext = Vec::new();
+    let info = Decompressor::new().decompress_into(&mut reader, &mut plaintext)?;
+    (plaintext, info)
+}
+
+/// Like `decompress_frame`, but also verifies the xxhash64 checksum frame that must follow the frame
+/// (see `CompressionSettings::xxhash64_checksum`).
+///
+/// The other functions never look past the end of the frame, so they don't check the xxhash64.
+#[cfg(feature = "xxhash64")]
+#[throws]
+pub fn decompress_frame_xxh64<R: Read>(mut reader: R) -> Vec<u8> {
+    let plaintext = decompress_frame(&mut reader)?;
+    verify_xxh64_frame(reader, &plaintext)?;
+    plaintext
+}
+
+/// Like `decompress_frame`, but for a frame that is entirely in memory.
+///
+/// Rather than going through `Read`, this walks the blocks by slicing `compressed`,
+/// so compressed blocks are decoded right where they are instead of being copied into a buffer first.
+/// Anything after the frame is ignored. Frames with a dictionary id fail with `DictionaryNotSupported`.
+#[throws]
+pub fn decompress_frame_slice(compressed: &[u8]) -> Vec<u8> {
+    let mut input = compressed;
+    let info = FrameInfo::read(&mut input, ReservedBits::Strict, Checksums::Verify, None)?;
+    if let Some(id) = info.dictionary_id {
+        throw!(Error::DictionaryNotSupported(id));
+    }
+    let block_maxsize = info.block_maxsize;
+    let mut content_hasher = if info.content_checksum() { Some(XxHash32::with_seed(0)) } else { None };
+    // LZ4 can't expand data by more than a factor of 255, so a bogus content size can't make us allocate too much
+    let capacity = info.content_size.map_or(0, |size| cmp::min(size, input.len() as u64 * 255));
+    let mut output = Vec::with_capacity(capacity.try_into().unwrap_or(0));
+    loop {
+        let block_length = LE::read_u32(take_bytes(&mut input, 4)?);
+        if block_length == 0 {
+            break;
+        }
+        let length: usize = (block_length & !INCOMPRESSIBLE).try_into().or(Err(Error::BlockLengthOverflow))?;
+        if length > block_maxsize {
+            throw!(Error::BlockSizeOverflow);
+        }
+        let data = take_bytes(&mut input, length)?;
+        if info.block_checksums() {
+            let checksum = LE::read_u32(take_bytes(&mut input, 4)?);
+            let mut hasher = XxHash32::with_seed(0);
+            hasher.write(data);
+            if hasher.finish() != checksum.into() {
+                throw!(Error::BlockChecksumFail);
+            }
+        }
+
+        let block_start = output.len();
+        if block_length & INCOMPRESSIBLE != 0 {
+            output.extend_from_slice(data);
+        } else if info.independent_blocks() {
+            raw::decompress_raw_append(data, &[], &mut output, block_maxsize)?;
+        } else {
+            // with linked blocks, everything decoded so far is the window
+            raw::decompress_raw(data, &[], &mut output, block_start + block_maxsize)?;
+        }
+        if output.len() - block_start > block_maxsize {
+            throw!(Error::BlockSizeOverflow);
+        }
+        if let Some(hasher) = content_hasher.as_mut() {
+            hasher.write(&output[block_start..]);
+        }
+    }
+
+    if let Some(hasher) = content_hasher {
+        let checksum = LE::read_u32(take_bytes(&mut input, 4)?);
+        if hasher.finish() != checksum.into() {
+            throw!(Error::FrameChecksumFail);
+        }
+    }
+    if let Some(expected) = info.content_size.filter(|&size| size != output.len() as u64) {
+        throw!(Error::ContentSizeMismatch { expected, actual: output.len() as u64 });
+    }
+    output
+}
+
+/// Split the first `len` bytes off `input`, failing with `UnexpectedEof` if there aren't that many.
+#[throws]
+fn take_bytes<'a>(input: &mut &'a [u8], len: usize) -> &'a [u8] {
+    if input.len() < len {
+        throw!(io::Error::from(ErrorKind::UnexpectedEof));
+    }
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+    taken
+}
+
+/// What `decompress_frame_with_report` found in a frame.
+#[derive(Clone, Debug)]
+pub struct DecompressionReport {
+    /// The information from the frame header.
+    pub frame_info: FrameInfo,
+    pub blocks: Vec<BlockReport>,
+    /// The size of the entire frame, including the header, block headers and checksums.
+    pub compressed_bytes: u64,
+    pub decompressed_bytes: u64,
+    /// Whether the frame has a content checksum (which was verified).
+    pub content_checksum: bool,
+}
+
+/// A single block in a `DecompressionReport`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockReport {
+    /// The size of the block data, not counting its length field and checksum.
+    pub compressed_size: usize,
+    pub decompressed_size: usize,
+    /// Whether the block was stored rather than compressed.
+    pub stored: bool,
+    /// Whether the block has a checksum (which was verified).
+    pub checksum: bool,
+}
+
+/// Like `decompress_frame_full`, but reports the details of every block.
+///
+/// This is meant for analyzing frames, e.g. to find out which encoder produced them or why they are slow to decode.
+#[throws]
+pub fn decompress_frame_with_report<R: Read>(mut reader: R) -> (Vec<u8>, DecompressionReport) {
+    let mut frame_reader = LZ4FrameReader::new(&mut reader)?;
+    let frame_info = frame_reader.info().clone();
+    let checksum_len = if frame_info.block_checksums() { 4 } else { 0 };
+    let mut plaintext = Vec::new();
+    let mut blocks = Vec::new();
+    let content_checksum = loop {
+        // the length of a block is usually read along with the previous block, so it's counted there
+        let compressed_before = frame_reader.compressed_bytes_read - if frame_reader.next_block_length.is_some() { 4 } else { 0 };
+        match frame_reader.decode_block_into(&mut plaintext, &[])? {
+            BlockOutcome::EndOfFrame { checksum_verified } => break checksum_verified,
+            BlockOutcome::Data { len, stored } => blocks.push(BlockReport {
+                compressed_size: (frame_reader.compressed_bytes_read - compressed_before) as usize - 8 - checksum_len,
+                decompressed_size: len,
+                stored,
+                checksum: checksum_len != 0,
+            }),
+        }
+    };
+    let report = DecompressionReport {
+        frame_info,
+        blocks,
+        compressed_bytes: frame_reader.compressed_bytes_read,
+        decompressed_bytes: frame_reader.decompressed_bytes_written,
+        content_checksum,
+    };
+    (plaintext, report)
+}
+
+/// Verify the xxhash64 checksum frame following an LZ4 frame.
+#[cfg(feature = "xxhash64")]
+#[throws]
+fn verify_xxh64_frame<R: Read>(mut reader: R, plaintext: &[u8]) {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != XXH64_FRAME_MAGIC.to_le_bytes() {
+        throw!(Error::Xxh64ChecksumMissing);
+    }
+
+    let size = reader.read_u32::<LE>()?;
+    if size as usize != XXH64_FRAME_TAG.len() + 8 {
+        throw!(Error::Xxh64ChecksumMissing);
+    }
+    let mut tag = [0u8; 4];
+    reader.read_exact(&mut tag)?;
+    let checksum = reader.read_u64::<LE>()?;
+    if tag != XXH64_FRAME_TAG {
+        throw!(Error::Xxh64ChecksumMissing);
+    }
+
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(plaintext);
+    if hasher.finish() != checksum {
+        throw!(Error::Xxh64ChecksumFail);
+    }
+}
+
+
+/// Decompress a single raw LZ4 block (no frame around it), e.g. one made by `CompressionSettings::compress_single_block`.
+///
+/// `dict` is the dictionary (or whatever data precedes the block), pass an empty slice if there is none.
+/// Decompressing more than `max_output` bytes fails with `raw::DecodeError::MemoryLimitExceeded`.
+#[throws]
+pub fn decompress_single_block(compressed: &[u8], dict: &[u8], max_output: usize) -> Vec<u8> {
+    let mut output = Vec::new();
+    raw::decompress_raw(compressed, dict, &mut output, max_output)?;
+    // decompress_raw's limit is not strict for literals
+    if output.len() > max_output {
+        throw!(raw::DecodeError::MemoryLimitExceeded { source_offset: compressed.len() });
+    }
+    output
+}
+
+/// A block of a frame with independent blocks, read but not decoded yet.
+#[cfg(feature = "parallel")]
+struct RawBlock {
+    data: Vec<u8>,
+    stored: bool,
+    checksum: Option<u32>,
+}
+#[cfg(feature = "parallel")]
+impl RawBlock {
+    /// Read the data and checksum of a block whose length field (`block_length`) has already been read.
+    #[throws]
+    fn read<R: Read>(reader: &mut R, block_length: u32, info: &FrameInfo) -> Self {
+        let length: usize = (block_length & !INCOMPRESSIBLE).try_into().or(Err(Error::BlockLengthOverflow))?;
+        if length > info.block_maxsize {
+            throw!(Error::BlockSizeOverflow);
+        }
+        let mut data = vec![0; length];
+        reader.read_exact(&mut data)?;
+        let checksum = if info.block_checksums() { Some(reader.read_u32::<LE>()?) } else { None };
+        RawBlock { data, stored: block_length & INCOMPRESSIBLE != 0, checksum }
+    }
+
+    #[throws]
+    fn decode(self, block_maxsize: usize) -> Vec<u8> {
+        if let Some(checksum) = self.checksum {
+            let mut hasher = XxHash32::with_seed(0);
+            hasher.write(&self.data);
+            if hasher.finish() != checksum.into() {
+                throw!(Error::BlockChecksumFail);
+            }
+        }
+        if self.stored {
+            return self.data;
+        }
+        let mut output = Vec::with_capacity(block_maxsize);
+        raw::decompress_raw(&self.data, &[], &mut output, block_maxsize)?;
+        if output.len() > block_maxsize {
+            throw!(Error::BlockSizeOverflow);
+        }
+        output
+    }
+}
+
+/// Read the header of a frame that we are going to decode in parallel, failing if that's not possible.
+#[cfg(feature = "parallel")]
+#[throws]
+fn read_parallel_header<R: Read>(reader: &mut R) -> FrameInfo {
+    let info = FrameInfo::read(reader, ReservedBits::Strict, Checksums::Verify, None)?;
+    if !info.independent_blocks() {
+        throw!(Error::LinkedBlocks);
+    }
+    if let Some(id) = info.dictionary_id {
+        throw!(Error::DictionaryNotSupported(id));
+    }
+    info
+}
+
+/// Decompress a frame on `threads` threads and write the plaintext to `writer`, returning the number of bytes written.
+///
+/// This first scans the frame for its blocks, seeking over their data, and checks that the frame ends properly.
+/// Then the blocks are read and handed to the threads, which decode them and write them straight to their offset
+/// in `writer` (relative to where `writer` was at the start). All blocks but the last one usually have the full
+/// block size, so that offset is known in advance; if the frame doesn't specify its content size or a block turns out
+/// shorter, the calling thread writes the affected blocks where they belong. The content checksum is verified last,
+/// over the blocks in order. Afterwards, `reader` is positioned after the frame and `writer` after the plaintext.
+///
+/// This only works for frames with independent blocks (the default), others fail with `LinkedBlocks`.
+/// Frames that need a dictionary fail with `DictionaryNotSupported`. Up to two blocks per thread are in memory at once.
+/// On error, `writer` contains garbage.
+#[cfg(feature = "parallel")]
+#[throws]
+pub fn parallel_decompress<R: Read + Seek, W: Write + Seek + Send>(mut reader: R, mut writer: W, threads: usize) -> u64 {
+    let info = read_parallel_header(&mut reader)?;
+    let checksum_len = if info.block_checksums() { 4 } else { 0 };
+
+    // the offset of every block's length field
+    let mut blocks = Vec::new();
+    loop {
+        let offset = reader.stream_position()?;
+        let block_length = reader.read_u32::<LE>()?;
+        if block_length == 0 {
+            break;
+        }
+        let length = u64::from(block_length & !INCOMPRESSIBLE);
+        if length > info.block_maxsize as u64 {
+            throw!(Error::BlockSizeOverflow);
+        }
+        blocks.push(offset);
+        reader.seek(SeekFrom::Current((length + checksum_len) as i64))?;
+    }
+    let checksum = if info.content_checksum() { Some(reader.read_u32::<LE>()?) } else { None };
+    let frame_end = reader.stream_position()?;
+
+    let start = writer.stream_position().map_err(Error::OutputError)?;
+    let writer = Mutex::new(writer);
+    let write_at = |offset: u64, block: &[u8]| {
+        let mut writer = writer.lock().expect("a decoding thread panicked");
+        writer.seek(SeekFrom::Start(start + offset))?;
+        writer.write_all(block)
+    };
+    let block_maxsize = info.block_maxsize as u64;
+    // without the content size, a short block could leave garbage after the plaintext
+    let fits = |index: u64, block: &[u8]| info.content_size.is_some_and(|size| index * block_maxsize + block.len() as u64 <= size);
+    let mut blocks = blocks.into_iter();
+    let (bytes_written, content_hash) = decode_blocks_parallel(&info, &DecompressionLimits::default(), threads, || {
+        let offset = match blocks.next() {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+        reader.seek(SeekFrom::Start(offset))?;
+        let block_length = reader.read_u32::<LE>()?;
+        Ok(Some(RawBlock::read(&mut reader, block_length, &info)?))
+    }, |index, block| {
+        if fits(index, block) {
+            write_at(index * block_maxsize, block)?;
+        }
+        Ok(())
+    }, |index, offset, block| {
+        if offset != index * block_maxsize || !fits(index, block) {
+            write_at(offset, block)?;
+        }
+        Ok(())
+    })?;
+    check_frame_end(&info, content_hash, checksum, bytes_written)?;
+
+    reader.seek(SeekFrom::Start(frame_end))?;
+    let mut writer = writer.into_inner().expect("a decoding thread panicked");
+    writer.seek(SeekFrom::Start(start + bytes_written)).map_err(Error::OutputError)?;
+    bytes_written
+}
+
+/// Decompress a frame into a file on `threads` threads, returning the number of bytes written.
+///
+/// If the frame has independent blocks and specifies its content size, the file is resized to that size up front
+/// and the threads write every block they decode straight to where it belongs, so (unlike `parallel_decompress`)
+/// `reader` doesn't need to be seekable. Otherwise, this falls back to decoding the frame the usual way.
+/// Either way, all checksums are verified. Frames that need a dictionary fail with `DictionaryNotSupported`.
+///
+/// The file is created if it doesn't exist and truncated if it does. On error, it is cut back to the plaintext
+/// that was decoded (in order) before the error.
+#[cfg(all(feature = "parallel", any(unix, windows)))]
+#[throws]
+pub fn decompress_to_file_parallel<R: Read, P: AsRef<Path>>(reader: R, path: P, threads: usize) -> u64 {
+    decompress_to_file_parallel_with_limits(reader, path, threads, DecompressionLimits::default())?
+}
+
+/// Like `decompress_to_file_parallel`, but enforces the given resource limits.
+///
+/// The header is checked against them before the file is created, so a frame that declares a larger content size
+/// than `max_output` never gets to resize the file.
+#[cfg(all(feature = "parallel", any(unix, windows)))]
+#[throws]
+pub fn decompress_to_file_parallel_with_limits<R: Read, P: AsRef<Path>>(mut reader: R, path: P, threads: usize, limits: DecompressionLimits) -> u64 {
+    let info = FrameInfo::read(&mut reader, ReservedBits::Strict, Checksums::Verify, None)?;
+    if let Some(id) = info.dictionary_id {
+        throw!(Error::DictionaryNotSupported(id));
+    }
+    if limits.max_block_size.is_some_and(|max| info.block_maxsize > max) {
+        throw!(Error::BlockSizeLimitExceeded);
+    }
+    if limits.max_output.is_some_and(|max| info.content_size.is_some_and(|size| size > max)) {
+        throw!(Error::OutputLimitExceeded);
+    }
+    let file = File::create(path).map_err(Error::OutputError)?;
+    let mut valid_len = 0;
+    let result = decode_to_file_parallel(reader, &file, info, limits, threads, &mut valid_len);
+    if result.is_err() {
+        // neither the declared content size nor blocks that were written out of order should survive the error
+        let _ = file.set_len(valid_len);
+    }
+    result?
+}
+
+/// The body of `decompress_to_file_parallel_with_limits`, which keeps `valid_len` at the length of the plaintext
+/// that has been written in order so far.
+#[cfg(all(feature = "parallel", any(unix, windows)))]
+#[throws]
+fn decode_to_file_parallel<R: Read>(mut reader: R, file: &File, info: FrameInfo, limits: DecompressionLimits, threads: usize, valid_len: &mut u64) -> u64 {
+    let next_block = |reader: &mut R| -> Result<Option<RawBlock>, Error> {
+        match reader.read_u32::<LE>()? {
+            0 => Ok(None),
+            block_length => Ok(Some(RawBlock::read(reader, block_length, &info)?)),
+        }
+    };
+    let (bytes_written, content_hash) = match info.content_size {
+        Some(content_size) if info.independent_blocks() => {
+            file.set_len(content_size).map_err(Error::OutputError)?;
+            // all blocks but the last one usually have the full block size, so we know where they go
+            let block_maxsize = info.block_maxsize as u64;
+            let fits = |index: u64, block: &[u8]| index * block_maxsize + block.len() as u64 <= content_size;
+            decode_blocks_parallel(&info, &limits, threads, || next_block(&mut reader), |index, block| {
+                if fits(index, block) {
+                    write_all_at(file, block, index * block_maxsize)?;
+                }
+                Ok(())
+            }, |index, offset, block| {
+                // if an earlier block was shorter, this one belongs somewhere else (and the range is checked above)
+                if offset != index * block_maxsize || !fits(index, block) {
+                    write_all_at(file, block, offset)?;
+                }
+                *valid_len = offset + block.len() as u64;
+                Ok(())
+            })?
+        }
+        _ => {
+            let mut writer = io::BufWriter::new(file);
+            if info.independent_blocks() {
+                let result = decode_blocks_parallel(&info, &limits, threads, || next_block(&mut reader), |_, _| Ok(()), |_, _, block| {
+                    writer.write_all(block)?;
+                    *valid_len += block.len() as u64;
+                    Ok(())
+                })?;
+                writer.flush().map_err(Error::OutputError)?;
+                result
+            } else {
+                let mut frame_reader = LZ4FrameReader::new_lazy(reader);
+                frame_reader.limits = limits;
+                frame_reader.start_frame(info)?;
+                let mut block = Vec::new();
+                loop {
+                    block.clear();
+                    if let BlockOutcome::EndOfFrame { .. } = frame_reader.decode_block_ex(&mut block, &[])? {
+                        break;
+                    }
+                    writer.write_all(&block).map_err(Error::OutputError)?;
+                    *valid_len += block.len() as u64;
+                }
+                writer.flush().map_err(Error::OutputError)?;
+                return frame_reader.decompressed_bytes_written();
+            }
+        }
+    };
+    let checksum = if info.content_checksum() { Some(reader.read_u32::<LE>()?) } else { None };
+    check_frame_end(&info, content_hash, checksum, bytes_written)?;
+    bytes_written
+}
+
+#[cfg(all(feature = "parallel", unix))]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(all(feature = "parallel", windows))]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_write(file, buf, offset) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Check the content checksum (`content_hash` is what we computed, `checksum` what the frame says) and size
+/// of a frame that was decoded in parallel.
+#[cfg(feature = "parallel")]
+#[throws]
+fn check_frame_end(info: &FrameInfo, content_hash: Option<u64>, checksum: Option<u32>, bytes_written: u64) {
+    if content_hash != checksum.map(u64::from) {
+        throw!(Error::FrameChecksumFail);
+    }
+    if let Some(expected) = info.content_size.filter(|&size| size != bytes_written) {
+        throw!(Error::ContentSizeMismatch { expected, actual: bytes_written });
+    }
+}
+
+/// Decode the blocks of a frame with independent blocks on `threads` threads,
+/// returning the number of bytes decoded and (if the frame has a content checksum) their hash.
+///
+/// The calling thread gets the blocks from `next_block` until it returns `None`.
+/// Each thread passes the blocks it decodes to `place` along with their index, then the calling thread
+/// passes them to `consume` in order, along with their index and offset in the plaintext.
+/// Errors from both are output errors. The block count and output limits are checked before
+/// a block is handed to a thread or to `consume`, respectively.
+#[cfg(feature = "parallel")]
+#[throws]
+fn decode_blocks_parallel<N, P, C>(info: &FrameInfo, limits: &DecompressionLimits, threads: usize, mut next_block: N, place: P, mut consume: C) -> (u64, Option<u64>)
+where
+    N: FnMut() -> Result<Option<RawBlock>, Error>,
+    P: Fn(u64, &[u8]) -> io::Result<()> + Sync,
+    C: FnMut(u64, u64, &[u8]) -> io::Result<()>,
+{
+    let threads = cmp::max(threads, 1);
+    let block_maxsize = info.block_maxsize;
+
+    let (work_sender, work_receiver) = mpsc::channel::<(u64, RawBlock)>();
+    let (done_sender, done_receiver) = mpsc::channel();
+    let work_receiver = Mutex::new(work_receiver);
+    thread::scope(|scope| -> Result<(u64, Option<u64>), Error> {
+        for _ in 0..threads {
+            let work_receiver = &work_receiver;
+            let done_sender = done_sender.clone();
+            let place = &place;
+            scope.spawn(move || loop {
+                // the lock is only held while waiting for work, not while decoding
+                let work = work_receiver.lock().expect("a decoding thread panicked").recv();
+                match work {
+                    Ok((index, block)) => {
+                        let block = block.decode(block_maxsize)
+                            .and_then(|block| place(index, &block).map(|()| block).map_err(Error::OutputError));
+                        if done_sender.send((index, block)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break, // no more blocks
+                }
+            });
+        }
+        drop(done_sender);
+
+        // returning (even with an error) drops work_sender, which makes the threads exit
+        let mut work_sender = Some(work_sender);
+        let mut blocks_read = 0;
+        let mut blocks_written = 0;
+        let mut finished_blocks = BTreeMap::new();
+        let mut content_hasher = if info.content_checksum() { Some(XxHash32::with_seed(0)) } else { None };
+        let mut bytes_written = 0;
+        loop {
+            while let Some(sender) = work_sender.as_ref().filter(|_| blocks_read - blocks_written < 2 * threads as u64) {
+                match next_block()? {
+                    Some(block) => {
+                        if limits.max_blocks.is_some_and(|max| blocks_read >= max) {
+                            return Err(Error::BlockCountLimitExceeded);
+                        }
+                        sender.send((blocks_read, block)).expect("all decoding threads panicked");
+                        blocks_read += 1;
+                    }
+                    None => work_sender = None,
+                }
+            }
+            if blocks_written == blocks_read {
+                break;
+            }
+
+            // blocks can finish out of order, but we must write them in order
+            let block = loop {
+                if let Some(block) = finished_blocks.remove(&blocks_written) {
+                    break block;
+                }
+                let (index, block) = done_receiver.recv().expect("a decoding thread panicked");
+                finished_blocks.insert(index, block);
+            }?;
+            if limits.max_output.is_some_and(|max| bytes_written + block.len() as u64 > max) {
+                return Err(Error::OutputLimitExceeded);
+            }
+            if let Some(hasher) = content_hasher.as_mut() {
+                hasher.write(&block);
+            }
+            consume(blocks_written, bytes_written, &block).map_err(Error::OutputError)?;
+            bytes_written += block.len() as u64;
+            blocks_written += 1;
+        }
+        Ok((bytes_written, content_hasher.map(|hasher| hasher.finish())))
+    })?
+}
+
+/// Decompress a frame and write the plaintext to `writer`, returning the number of bytes written.
+///
+/// See `DecompressionSettings::decompress`, this just uses the default settings.
+#[throws]
+pub fn decompress_frame_to_writer<R: Read, W: Write>(reader: R, writer: W) -> u64 {
+    DecompressionSettings::default().decompress(reader, writer)?
+}
+
+/// Decompress a frame into a file, returning the number of bytes written.
+///
+/// See `DecompressionSettings::decompress_to_file`, this just uses the default settings.
+#[throws]
+pub fn decompress_to_file<R: Read, P: AsRef<Path>>(reader: R, path: P) -> u64 {
+    DecompressionSettings::default().decompress_to_file(reader, path)?
+}
+
+/// Decompress only the first `n` bytes of a frame (or less, if the frame is shorter).
+///
+/// Blocks are decoded one by one and reading stops as soon as at least `n` bytes are available,
+/// so the rest of the frame is never even read from `reader`.
+/// Note that this means the **content checksum is not verified** (unless the frame happens to end early),
+/// because that would require decoding everything. Block checksums are still verified as usual.
+#[throws]
+pub fn decompress_frame_prefix<R: Read>(reader: R, n: usize) -> Vec<u8> {
+    let mut plaintext = Vec::new();
+    LZ4FrameReader::new(reader)?.decode_prefix(n, &mut plaintext)?;
+    plaintext.truncate(n);
+    plaintext
+}
+
+/// Check the checksum of the frame header at the start of `header` (beginning with the magic number).
+///
+/// Anything after the header is ignored. Unlike `FrameInfo::parse`, this doesn't care whether the frame could
+/// actually be decoded (version, reserved bits and block size), it only checks the magic number and the checksum.
+#[throws]
+pub fn verify_header_checksum(header: &[u8]) {
+    let truncated = || Error::InputError(ErrorKind::UnexpectedEof.into());
+    let magic = header.get(..4).map(LE::read_u32).ok_or_else(truncated)?;
+    if magic != MAGIC {
+        throw!(Error::WrongMagic(magic));
+    }
+    let flags = Flags::from_bits_truncate(*header.get(4).ok_or_else(truncated)?);
+    let header_len = 2 + if flags.content_size() { 8 } else { 0 } + if flags.dictionary_id() { 4 } else { 0 };
+    let descriptor = header.get(4..4 + header_len).ok_or_else(truncated)?;
+    let expected = *header.get(4 + header_len).ok_or_else(truncated)?;
+
+    let mut hasher = XxHash32::with_seed(0);
+    hasher.write(descriptor);
+    let actual = (hasher.finish() >> 8) as u8;
+    if expected != actual {
+        throw!(Error::HeaderChecksumFail { expected, actual, header_len });
+    }
+}
+
+/// The reader returned by `resync_to_next_frame`: what it read past the start of the frame, then the rest of the input.
+pub type Resynced<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+/// Scan `reader` for the start of the next frame (an LZ4 frame or a skippable frame).
+/// Returns how many bytes were skipped, or `None` if there is no frame until the end of the input.
+///
+/// This is for salvaging what's left of a damaged stream of concatenated frames: after a decoding error,
+/// call this and carry on with `LZ4FrameReader::new` on the reader it returns. As `reader` can't be rewound,
+/// that reader first replays what we read past the start of the frame and then continues with `reader`.
+/// The magic number might just as well appear by chance in the damaged data, so an LZ4 frame is only
+/// accepted if its header checksum is correct. Skippable frames don't have one and are taken at face value.
+/// If the reader is already at the start of a frame, nothing is skipped.
+pub fn resync_to_next_frame<R: Read>(mut reader: R) -> io::Result<Option<(u64, Resynced<R>)>> {
+    /// Magic, flags, block descriptor, content size, dictionary id and checksum.
+    const MAX_HEADER_LEN: usize = 19;
+    const CHUNK_SIZE: u64 = 64 * 1024;
+
+    // the input from `window_start` on that we haven't ruled out yet
+    let mut window = Vec::new();
+    let mut window_start = 0;
+    let mut eof = false;
+    loop {
+        let mut pos = 0;
+        // look at every position that has enough data after it for any header
+        while window.len() - pos >= MAX_HEADER_LEN || (eof && window.len() - pos >= 4) {
+            let candidate = &window[pos..];
+            let found = match LE::read_u32(candidate) {
+                MAGIC => FrameInfo::read(&mut &candidate[..], ReservedBits::Strict, Checksums::Verify, None).is_ok(),
+                magic => magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC,
+            };
+            if found {
+                window.drain(..pos);
+                return Ok(Some((window_start + pos as u64, io::Cursor::new(window).chain(reader))));
+            }
+            pos += 1;
+        }
+        if eof {
+            return Ok(None);
+        }
+
+        window.drain(..pos);
+        window_start += pos as u64;
+        let len = window.len();
+        reader.by_ref().take(CHUNK_SIZE).read_to_end(&mut window)?;
+        eof = window.len() == len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::convert::TryInto;
+    use std::io::{self, Read, Write};
+    use std::rc::Rc;
+    use std::thread;
+    use super::{LZ4FrameReader, LZ4FrameDecompressor, LZ4FrameFollower, Decompressor, DictionaryProvider, DecoderDictionary, MaybeCompressedReader, DecompressionSettings, BlockOutcome, DecompressionError, DecompressionLimits, decompress_frame_with_limits, decompress_frame, decompress_frame_full, decompress_frame_prefix, decompress_frame_to_writer, decompress_frame_slice, decompress_frame_with_report, decompress_single_block, resync_to_next_frame, verify_header_checksum, decompress_to_file, FrameInfo};
+    use crate::raw::DecodeError;
+    use crate::framed::{CompressionSettings, ReservedBits};
+    use crate::framed::header::ParseError;
+    use crate::test_data::{compressible, compressible_byte, compressible_changing_every, noise, Noise};
+    #[cfg(feature = "xxhash64")]
+    use super::decompress_frame_xxh64;
+
+    fn compress(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        CompressionSettings::default().compress(input, &mut output).unwrap();
+        output
+    }
+
+    /// A source that other code can keep pushing data into.
+    #[derive(Clone, Default)]
+    struct SharedSource(Rc<RefCell<VecDeque<u8>>>);
+    impl Read for SharedSource {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.borrow_mut().read(buf)
+        }
+    }
+
+    #[test]
+    fn lazy_reader_waits_for_data() {
+        let source = SharedSource::default();
+        let mut reader = LZ4FrameReader::new_lazy(source.clone());
+        assert!(reader.frame_info().is_none());
+        assert_eq!((reader.block_size(), reader.frame_size(), reader.dictionary_id()), (None, None, None));
+        assert_eq!(reader.remaining_blocks_hint(), None);
+
+        let plaintext = b"lazy readers only read once they are asked to".repeat(10);
+        source.0.borrow_mut().extend(compress(&plaintext));
+
+        assert_eq!(reader.read_header().unwrap().block_size(), 4 * 1024 * 1024);
+        assert_eq!(reader.block_size(), Some(4 * 1024 * 1024));
+        let mut output = Vec::new();
+        reader.into_read().read_to_end(&mut output).unwrap();
+        assert_eq!(output, plaintext);
+    }
+
+    #[test]
+    fn prefix_stops_reading_early() {
+        let plaintext: Vec<u8> = compressible(200_000);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).compress(plaintext.as_slice(), &mut compressed).unwrap();
+
+        let mut input = compressed.as_slice();
+        let prefix = decompress_frame_prefix(input.by_ref(), 1000).unwrap();
+        assert_eq!(prefix, &plaintext[..1000]);
+        // we only needed the first of four blocks
+        assert!(input.len() > compressed.len() / 2);
+
+        assert_eq!(decompress_frame_prefix(compressed.as_slice(), 1 << 20).unwrap(), plaintext);
+
+        // whole blocks only, and the reader can carry on afterwards
+        let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+        let mut output = b"before".to_vec();
+        reader.decode_prefix(70_000, &mut output).unwrap();
+        assert_eq!(output.len(), 6 + 2 * 64 * 1024);
+        reader.decode_prefix(0, &mut output).unwrap();
+        assert_eq!(output.len(), 6 + 2 * 64 * 1024);
+        reader.into_read().read_to_end(&mut output).unwrap();
+        assert_eq!(&output[6..], &plaintext[..]);
+    }
+
+    /// What the reference implementation produces for empty input.
+    static EMPTY_FRAME: &[u8] = &[0x04, 0x22, 0x4D, 0x18, 0x64, 0x40, 0xA7, 0, 0, 0, 0, 0x05, 0x5D, 0xCC, 0x02];
+
+    #[test]
+    fn empty_content_with_checksum() {
+        assert_eq!(decompress_frame(EMPTY_FRAME).unwrap(), b"");
+
+        let compressed = compress(b"");
+        // the xxhash32 of nothing
+        assert_eq!(compressed[compressed.len() - 4..], [0x05, 0x5D, 0xCC, 0x02]);
+        assert_eq!(decompress_frame(compressed.as_slice()).unwrap(), b"");
+
+        let mut corrupted = EMPTY_FRAME.to_vec();
+        *corrupted.last_mut().unwrap() ^= 1;
+        let mut reader = LZ4FrameReader::new(corrupted.as_slice()).unwrap();
+        match reader.decode_block(&mut Vec::new(), &[]) {
+            Err(DecompressionError::FrameChecksumFail) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    /// Frames with nothing in them, all accepted by the reference `lz4 -d` (v1.9.4).
+    static EMPTY_FRAMES: &[(&[u8], &[u8])] = &[
+        (EMPTY_FRAME, b""),
+        // content size 0 (the CLI never writes this, but we do)
+        (&[0x04, 0x22, 0x4D, 0x18, 0x6C, 0x70, 0, 0, 0, 0, 0, 0, 0, 0, 0x03, 0, 0, 0, 0, 0x05, 0x5D, 0xCC, 0x02], b""),
+        // a compressed block that decodes to nothing (a lone token without literals or match)
+        (&[0x04, 0x22, 0x4D, 0x18, 0x64, 0x40, 0xA7, 1, 0, 0, 0, 0x00, 0, 0, 0, 0, 0x05, 0x5D, 0xCC, 0x02], b""),
+        // an empty compressed block, "abc" as literals, an empty stored block
+        (&[0x04, 0x22, 0x4D, 0x18, 0x64, 0x40, 0xA7, 1, 0, 0, 0, 0x00, 4, 0, 0, 0, 0x30, b'a', b'b', b'c',
+            0, 0, 0, 0x80, 0, 0, 0, 0, 0xFF, 0x53, 0xD1, 0x32], b"abc"),
+    ];
+
+    #[test]
+    fn empty_frames_and_blocks() {
+        for &(frame, plaintext) in EMPTY_FRAMES {
+            assert_eq!(decompress_frame(frame).unwrap(), plaintext);
+            assert_eq!(decompress_frame_prefix(frame, 10).unwrap(), plaintext);
+            let mut output = Vec::new();
+            Decompressor::new().decompress_into(frame, &mut output).unwrap();
+            assert_eq!(output, plaintext);
+
+            // tiny reads go through fill_buf, which must skip empty blocks rather than stop
+            let mut reader = LZ4FrameReader::new(frame).unwrap().into_read();
+            let mut output = Vec::new();
+            let mut byte = [0];
+            while reader.read(&mut byte).unwrap() == 1 {
+                output.push(byte[0]);
+            }
+            assert_eq!(output, plaintext);
+
+            let mut output = Vec::new();
+            let mut decompressor = LZ4FrameDecompressor::new(&mut output);
+            for &b in frame {
+                decompressor.write_all(&[b]).unwrap();
+            }
+            assert!(decompressor.is_finished());
+            decompressor.finish().unwrap();
+            assert_eq!(output, plaintext);
+        }
+        assert_eq!(LZ4FrameReader::new(EMPTY_FRAMES[1].0).unwrap().frame_size(), Some(0));
+
+        // we only ever write the end mark for empty input, no matter how it's fed to us
+        let mut output = Vec::new();
+        CompressionSettings::default().compress_with_size(io::Cursor::new([]), &mut output).unwrap();
+        assert_eq!(output, EMPTY_FRAMES[1].0);
+        let mut output = Vec::new();
+        let mut writer = CompressionSettings::default().streaming_compress(&mut output).unwrap();
+        writer.write_all(b"").unwrap();
+        writer.flush().unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(output, compress(b""));
+    }
+
+    #[test]
+    fn byte_counters() {
+        let plaintext: Vec<u8> = compressible(200_000);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).block_checksums(true)
+            .compress_with_size(io::Cursor::new(&plaintext), &mut compressed).unwrap();
+
+        let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+        assert_eq!(reader.compressed_bytes_read(), 15);
+        assert_eq!(reader.remaining_blocks_hint(), Some(4));
+        let mut block = Vec::new();
+        reader.decode_block(&mut block, &[]).unwrap();
+        assert_eq!(reader.decompressed_bytes_written(), 64 * 1024);
+        assert_eq!(reader.remaining_blocks_hint(), Some(3));
+
+        let mut reader = reader.into_read();
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        assert_eq!(reader.compressed_bytes_read(), compressed.len() as u64);
+        assert_eq!(reader.decompressed_bytes_written(), plaintext.len() as u64);
+        assert_eq!(reader.remaining_blocks_hint(), Some(0));
+        assert_eq!(LZ4FrameReader::new(compress(&plaintext).as_slice()).unwrap().remaining_blocks_hint(), None);
+    }
+
+    /// Counts the calls to `read`, i.e. the syscalls if the inner reader were a `File`.
+    struct CountingReader<R> {
+        inner: R,
+        reads: usize,
+    }
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            self.inner.read(buf)
+        }
+    }
+
+    #[test]
+    fn one_read_per_block() {
+        let plaintext: Vec<u8> = compressible(300_000);
+        for &block_checksums in &[false, true] {
+            let mut compressed = Vec::new();
+            CompressionSettings::default().block_size(64 * 1024).block_checksums(block_checksums)
+                .compress(plaintext.as_slice(), &mut compressed).unwrap();
+
+            let mut reader = CountingReader { inner: compressed.as_slice(), reads: 0 };
+            let mut output = Vec::new();
+            LZ4FrameReader::new(&mut reader).unwrap().into_read().read_to_end(&mut output).unwrap();
+            assert!(output == plaintext);
+            // the header (magic number, FLG, BD and checksum), the first block length,
+            // the five blocks (each with its checksum and the following length) and the content checksum
+            assert_eq!(reader.reads, 4 + 1 + 5 + 1);
+        }
+    }
+
+    #[test]
+    fn slice() {
+        let mut noise = Noise::default();
+        let plaintext: Vec<u8> = (0..300_000u32).map(|i| if i / 70_000 == 2 {
+            noise.byte()
+        } else {
+            compressible_byte(i, 1000)
+        }).collect();
+        for &(linked, checksums) in &[(false, false), (false, true), (true, false), (true, true)] {
+            let mut settings = CompressionSettings::default();
+            settings.block_size(64 * 1024).block_checksums(checksums).content_checksum(checksums);
+            if linked {
+                settings.linked_blocks();
+            }
+            let mut compressed = Vec::new();
+            settings.compress_with_size(io::Cursor::new(&plaintext), &mut compressed).unwrap();
+            assert!(decompress_frame_slice(&compressed).unwrap() == plaintext);
+
+            // trailing data is ignored, truncated frames fail
+            compressed.extend_from_slice(b"trailing data");
+            assert!(decompress_frame_slice(&compressed).unwrap() == plaintext);
+            for &len in &[3, 10, 20, 1000, compressed.len() - 17] {
+                assert!(matches!(decompress_frame_slice(&compressed[..len]), Err(DecompressionError::InputError(_))), "{}", len);
+            }
+        }
+        for &(frame, plaintext) in EMPTY_FRAMES {
+            assert_eq!(decompress_frame_slice(frame).unwrap(), plaintext);
+        }
+
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_checksums(true).compress(&plaintext[..1000], &mut compressed).unwrap();
+        let mut corrupted = compressed.clone();
+        corrupted[20] ^= 1;
+        assert!(matches!(decompress_frame_slice(&corrupted), Err(DecompressionError::BlockChecksumFail)));
+        *compressed.last_mut().unwrap() ^= 1;
+        assert!(matches!(decompress_frame_slice(&compressed), Err(DecompressionError::FrameChecksumFail)));
+
+        let mut compressed = Vec::new();
+        CompressionSettings::default().dictionary(3, &plaintext[..100]).compress(&plaintext[..1000], &mut compressed).unwrap();
+        assert!(matches!(decompress_frame_slice(&compressed), Err(DecompressionError::DictionaryNotSupported(3))));
+    }
+
+    /// A file that someone else keeps appending to. Reading at the end returns `WouldBlock` if `would_block` is set.
+    struct GrowingFile {
+        data: Rc<std::cell::RefCell<Vec<u8>>>,
+        pos: usize,
+        would_block: bool,
+    }
+    impl Read for GrowingFile {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let data = self.data.borrow();
+            if self.pos == data.len() && self.would_block {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let len = (&data[self.pos..]).read(buf)?;
+            self.pos += len;
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn follow() {
+        let plaintext: Vec<u8> = compressible(300_000);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).block_checksums(true)
+            .compress_with_size(io::Cursor::new(&plaintext), &mut compressed).unwrap();
+        compressed.extend_from_slice(&compress(b"the next frame"));
+
+        for &would_block in &[false, true] {
+            let data = Rc::new(std::cell::RefCell::new(Vec::new()));
+            let mut follower = LZ4FrameFollower::new(GrowingFile { data: data.clone(), pos: 0, would_block });
+            let mut output = Vec::new();
+            let mut outcomes = Vec::new();
+            // the producer writes a few bytes (across header, block and checksum boundaries) at a time
+            for chunk in compressed.chunks(1000) {
+                data.borrow_mut().extend_from_slice(chunk);
+                while let Some(outcome) = follower.decode_block(&mut output).unwrap() {
+                    outcomes.push(outcome);
+                    if follower.is_finished() {
+                        break;
+                    }
+                }
+            }
+            assert!(output == plaintext);
+            assert_eq!(outcomes.len(), 6);
+            assert_eq!(outcomes[5], BlockOutcome::EndOfFrame { checksum_verified: true });
+            assert_eq!(follower.frame_info().unwrap().frame_size(), Some(plaintext.len() as u64));
+
+            follower.next_frame();
+            let mut output = Vec::new();
+            while follower.decode_block(&mut output).unwrap() != Some(BlockOutcome::EndOfFrame { checksum_verified: true }) {}
+            assert_eq!(output, b"the next frame");
+            assert_eq!(follower.decode_block(&mut output).unwrap(), Some(BlockOutcome::EndOfFrame { checksum_verified: true }));
+            follower.next_frame();
+            assert_eq!(follower.decode_block(&mut output).unwrap(), None);
+        }
+
+        // errors still are errors
+        let mut corrupted = compress(b"corrupted");
+        corrupted[4] ^= 1;
+        assert!(LZ4FrameFollower::new(corrupted.as_slice()).decode_block(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn full_returns_frame_info() {
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_checksums(true).dictionary(42, b"dict")
+            .compress_with_size(io::Cursor::new(b"info"), &mut compressed).unwrap();
+
+        // too short to contain any matches, so we can decode it without the dictionary
+        let (plaintext, info) = decompress_frame_full(compressed.as_slice()).unwrap();
+        assert_eq!(plaintext, b"info");
+        assert_eq!(info.frame_size(), Some(4));
+        assert_eq!(info.dictionary_id(), Some(42));
+        assert!(info.requires_dictionary());
+        assert!(info.block_checksums());
+        assert!(info.independent_blocks() && info.supports_random_access());
+
+        let (plaintext, info) = decompress_frame_full(&compress(b"more info")[..]).unwrap();
+        assert_eq!(plaintext, b"more info");
+        assert_eq!(info.block_size(), 4 * 1024 * 1024);
+        assert_eq!(info.frame_size(), None);
+        assert_eq!(info.dictionary_id(), None);
+        assert!(info.content_checksum());
+        assert!(!info.block_checksums());
+        assert!(!info.requires_dictionary());
+
+        let mut compressed = Vec::new();
+        CompressionSettings::default().linked_blocks().compress(&b"linked"[..], &mut compressed).unwrap();
+        let info = decompress_frame_full(compressed.as_slice()).unwrap().1;
+        assert!(!info.independent_blocks() && !info.supports_random_access());
+    }
+
+    fn decode_all<R: Read>(mut reader: LZ4FrameReader<R>) -> Result<Vec<u8>, DecompressionError> {
+        let mut plaintext = Vec::new();
+        let mut block = Vec::new();
+        loop {
+            block.clear();
+            reader.decode_block(&mut block, &[])?;
+            if block.is_empty() {
+                return Ok(plaintext);
+            }
+            plaintext.extend_from_slice(&block);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let compressible: Vec<u8> = compressible(200_000);
+        let incompressible: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        for plaintext in &[compressible, incompressible] {
+            let mut compressed = Vec::new();
+            CompressionSettings::default().block_size(64 * 1024).compress(plaintext.as_slice(), &mut compressed).unwrap();
+            let with_limits = |limits: &DecompressionLimits| decode_all(LZ4FrameReader::with_limits(compressed.as_slice(), *limits)?);
+
+            assert_eq!(&with_limits(&DecompressionLimits::default()).unwrap(), plaintext);
+            assert_eq!(&with_limits(DecompressionLimits::default().max_output(200_000).max_blocks(4)).unwrap(), plaintext);
+            match with_limits(DecompressionLimits::default().max_output(199_999)) {
+                Err(DecompressionError::OutputLimitExceeded) => (),
+                other => panic!("unexpected result {:?}", other.map(|v| v.len())),
+            }
+            match with_limits(DecompressionLimits::default().max_output(1000)) {
+                Err(DecompressionError::OutputLimitExceeded) => (),
+                other => panic!("unexpected result {:?}", other.map(|v| v.len())),
+            }
+            match with_limits(DecompressionLimits::default().max_blocks(3)) {
+                Err(DecompressionError::BlockCountLimitExceeded) => (),
+                other => panic!("unexpected result {:?}", other.map(|v| v.len())),
+            }
+        }
+
+        let compressed = compress(b"tiny frame, huge blocks");
+        match LZ4FrameReader::with_limits(compressed.as_slice(), *DecompressionLimits::default().max_block_size(64 * 1024)) {
+            Err(DecompressionError::BlockSizeLimitExceeded) => (),
+            other => panic!("unexpected result {:?}", other.map(|r| r.block_size())),
+        }
+        assert!(decompress_frame_with_limits(compressed.as_slice(), *DecompressionLimits::default().max_block_size(4 << 20)).is_ok());
+    }
+
+    #[test]
+    fn max_block_size_checked_with_header() {
+        let mut frame = Vec::new();
+        CompressionSettings::default().linked_blocks().compress(&b"4 MiB blocks"[..], &mut frame).unwrap();
+        let mut settings = DecompressionSettings::default();
+        settings.limits(*DecompressionLimits::default().max_block_size(64 * 1024));
+
+        // nothing but the header is read
+        let mut input = frame.as_slice();
+        assert!(matches!(settings.frame_reader(&mut input), Err(DecompressionError::BlockSizeLimitExceeded)));
+        assert_eq!(input.len(), frame.len() - 7);
+
+        // and neither the block buffer nor the window have been allocated
+        let mut reader = LZ4FrameReader::new_lazy(frame.as_slice());
+        reader.limits = settings.limits;
+        assert!(matches!(reader.read_header(), Err(DecompressionError::BlockSizeLimitExceeded)));
+        assert_eq!((reader.read_buf.capacity(), reader.carryover_window.capacity()), (0, 0));
+    }
+
+    #[test]
+    fn read_by_reference() {
+        let plaintext = b"first half, second half".to_vec();
+        let compressed = compress(&plaintext);
+        let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap().into_read();
+
+        let mut first = [0u8; 11];
+        (&mut reader).read_exact(&mut first).unwrap();
+        let mut rest = Vec::new();
+        io::copy(&mut (&mut reader).take(100), &mut rest).unwrap();
+
+        assert_eq!(&first, b"first half,");
+        assert_eq!(rest, b" second half");
+        assert_eq!(reader.read(&mut first).unwrap(), 0);
+    }
+
+    #[test]
+    fn lazy_reader_reads_header_on_first_block() {
+        let plaintext = b"decode_block parses the header by itself";
+        let compressed = compress(plaintext);
+        let mut reader = LZ4FrameReader::new_lazy(compressed.as_slice());
+
+        let mut output = Vec::new();
+        reader.decode_block(&mut output, &[]).unwrap();
+        assert_eq!(output, plaintext);
+        assert!(reader.frame_info().is_some());
+    }
+
+    #[test]
+    fn push_decompressor() {
+        let plaintext: Vec<u8> = compressible_changing_every(200_000, 5000);
+        let mut compressed = Vec::new();
+        let mut writer = CompressionSettings::default()
+            .linked_blocks()
+            .block_checksums(true)
+            .block_size(64 * 1024)
+            .streaming_compress(&mut compressed)
+            .unwrap();
+        for chunk in plaintext.chunks(30_000) {
+            writer.write_all(chunk).unwrap();
+            writer.flush().unwrap();
+        }
+        writer.finish().unwrap();
+
+        for &fragment in &[1, 7, 4096, compressed.len()] {
+            let mut decompressor = LZ4FrameDecompressor::new(Vec::new());
+            for chunk in compressed.chunks(fragment) {
+                decompressor.write_all(chunk).unwrap();
+            }
+            assert!(decompressor.is_finished());
+            assert!(decompressor.frame_info().unwrap().block_checksums());
+            assert!(decompressor.finish().unwrap() == plaintext, "fragment size {}", fragment);
+        }
+    }
+
+    #[test]
+    fn push_decompressor_stops_at_frame_end() {
+        let mut input = compress(b"one frame");
+        input.extend_from_slice(b"something else");
+        let mut decompressor = LZ4FrameDecompressor::new(Vec::new());
+        let consumed = decompressor.write(&input).unwrap();
+        assert_eq!(&input[consumed..], b"something else");
+        assert_eq!(decompressor.write(&input[consumed..]).unwrap(), 0);
+        assert_eq!(decompressor.finish().unwrap(), b"one frame");
+    }
+
+    #[test]
+    fn push_decompressor_errors() {
+        let compressed = compress(b"this frame gets cut short");
+        let mut decompressor = LZ4FrameDecompressor::new(Vec::new());
+        decompressor.write_all(&compressed[..compressed.len() - 1]).unwrap();
+        assert!(!decompressor.is_finished());
+        assert!(matches!(decompressor.finish(), Err(DecompressionError::InputError(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+
+        let mut corrupted = compressed;
+        corrupted[0] ^= 1;
+        assert!(LZ4FrameDecompressor::new(Vec::new()).write_all(&corrupted).is_err());
+    }
+
+    #[cfg(feature = "xxhash64")]
+    #[test]
+    fn xxhash64_checksum() {
+        let plaintext = b"checked twice, just to be sure";
+        let mut compressed = Vec::new();
+        CompressionSettings::default().xxhash64_checksum(true).compress(&plaintext[..], &mut compressed).unwrap();
+        let frame_len = compress(plaintext).len();
+        // the regular frame is unchanged, the skippable frame follows it
+        assert_eq!(compressed[..frame_len], compress(plaintext)[..]);
+        assert_eq!(compressed[frame_len..][..4], [0x5E, 0x2A, 0x4D, 0x18]);
+        assert_eq!(decompress_frame_xxh64(compressed.as_slice()).unwrap(), plaintext);
+
+        // plain decompress_frame stops right at the end of the frame
+        let mut reader = compressed.as_slice();
+        assert_eq!(decompress_frame(&mut reader).unwrap(), plaintext);
+        assert_eq!(reader.len(), compressed.len() - frame_len);
+
+        assert!(matches!(decompress_frame_xxh64(&compressed[..frame_len]), Err(DecompressionError::InputError(_))));
+        let mut two_frames = compress(plaintext);
+        two_frames.extend_from_slice(&compress(plaintext));
+        assert!(matches!(decompress_frame_xxh64(two_frames.as_slice()), Err(DecompressionError::Xxh64ChecksumMissing)));
+
+        // corrupt the xxhash64 only
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(decompress_frame(compressed.as_slice()).is_ok());
+        assert!(matches!(decompress_frame_xxh64(compressed.as_slice()), Err(DecompressionError::Xxh64ChecksumFail)));
+    }
+
+    #[test]
+    fn single_block() {
+        let plaintext = b"a single block, a single block, a single block without a frame";
+        let dictionary = b"a single block without";
+        let (compressed, block) = CompressionSettings::default().dictionary(0, dictionary).compress_single_block(plaintext);
+        assert!(compressed);
+
+        assert_eq!(decompress_single_block(&block, dictionary, plaintext.len()).unwrap(), &plaintext[..]);
+        assert!(matches!(decompress_single_block(&block, dictionary, plaintext.len() - 1),
+            Err(DecompressionError::CodecError(DecodeError::MemoryLimitExceeded { .. }))));
+        assert!(decompress_single_block(&block, &[], plaintext.len()).is_err());
+
+        // literals only
+        assert!(matches!(decompress_single_block(&[0x50, 1, 2, 3, 4, 5], &[], 4),
+            Err(DecompressionError::CodecError(DecodeError::MemoryLimitExceeded { .. }))));
+    }
+
+    /// Flip the independent blocks flag, fixing up the header checksum.
+    /// Flip bits in the FLG (`index` 4) or BD (`index` 5) byte of a frame without content size or dictionary id.
+    fn toggle_header_bits(frame: &mut [u8], index: usize, mask: u8) {
+        frame[index] ^= mask;
+        let mut hasher = twox_hash::XxHash32::with_seed(0);
+        std::hash::Hasher::write(&mut hasher, &frame[4..6]);
+        frame[6] = (std::hash::Hasher::finish(&hasher) >> 8) as u8;
+    }
+
+    /// Flip the independent blocks flag.
+    fn relabel(frame: &mut [u8]) {
+        toggle_header_bits(frame, 4, 0x20);
+    }
+
+    #[test]
+    fn unknown_block_size() {
+        let plaintext = b"written by an ancient encoder ".repeat(20);
+        let mut frame = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).compress(plaintext.as_slice(), &mut frame).unwrap();
+        // block size code 4 (64 KiB) becomes 2
+        toggle_header_bits(&mut frame, 5, 0x60);
+        match LZ4FrameReader::new(frame.as_slice()) {
+            Err(DecompressionError::HeaderParseError(ParseError::UnimplementedBlocksize(2))) => (),
+            other => panic!("{:?}", other.err()),
+        }
+
+        let mut settings = DecompressionSettings::default();
+        let reader = settings.unknown_block_size(1024).frame_reader(frame.as_slice()).unwrap();
+        assert_eq!(reader.block_size(), Some(1024));
+        assert_eq!(decode_all(reader).unwrap(), plaintext);
+
+        // the block doesn't fit
+        let reader = settings.unknown_block_size(100).frame_reader(frame.as_slice()).unwrap();
+        assert!(decode_all(reader).is_err());
+        let mut limits = DecompressionLimits::default();
+        limits.max_block_size(512);
+        assert!(matches!(settings.unknown_block_size(1024).limits(limits).frame_reader(frame.as_slice()),
+            Err(DecompressionError::BlockSizeLimitExceeded)));
+    }
+
+    #[test]
+    fn mislabeled_block_independence() {
+        let plaintext = b"the second block references the first one: ".repeat(20);
+        let mut linked = Vec::new();
+        let mut writer = CompressionSettings::default().linked_blocks().streaming_compress(&mut linked).unwrap();
+        writer.write_all(&plaintext[..400]).unwrap();
+        writer.flush().unwrap();
+        writer.write_all(&plaintext[400..]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(decompress_frame(linked.as_slice()).unwrap(), plaintext);
+
+        relabel(&mut linked);
+        assert!(matches!(decode_all(LZ4FrameReader::new(linked.as_slice()).unwrap()),
+            Err(DecompressionError::CodecError(DecodeError::InvalidDeduplicationOffset { .. }))));
+
+        // the other way round is harmless: linked blocks may, but don't have to, reference previous blocks
+        let mut independent = compress(&plaintext);
+        relabel(&mut independent);
+        assert_eq!(decompress_frame(independent.as_slice()).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn block_outcomes() {
+        // stored "abc", an empty stored block, stored "def", end mark and content checksum
+        let mut frame = EMPTY_FRAME[..7].to_vec();
+        frame.extend_from_slice(&[3, 0, 0, 0x80, b'a', b'b', b'c', 0, 0, 0, 0x80, 3, 0, 0, 0x80, b'd', b'e', b'f', 0, 0, 0, 0]);
+        let mut hasher = twox_hash::XxHash32::with_seed(0);
+        std::hash::Hasher::write(&mut hasher, b"abcdef");
+        frame.extend_from_slice(&(std::hash::Hasher::finish(&hasher) as u32).to_le_bytes());
+
+        let mut reader = LZ4FrameReader::new(frame.as_slice()).unwrap();
+        let mut outcomes = Vec::new();
+        loop {
+            let outcome = reader.decode_block_ex(&mut Vec::new(), &[]).unwrap();
+            outcomes.push(outcome);
+            if let BlockOutcome::EndOfFrame { .. } = outcome {
+                break;
+            }
+        }
+        assert_eq!(outcomes, [
+            BlockOutcome::Data { len: 3, stored: true },
+            BlockOutcome::Data { len: 0, stored: true },
+            BlockOutcome::Data { len: 3, stored: true },
+            BlockOutcome::EndOfFrame { checksum_verified: true },
+        ]);
+        assert_eq!(reader.decode_block_ex(&mut Vec::new(), &[]).unwrap(), BlockOutcome::EndOfFrame { checksum_verified: true });
+
+        // the empty block doesn't end the stream early
+        assert_eq!(decompress_frame(frame.as_slice()).unwrap(), b"abcdef");
+        assert_eq!(decompress_frame_prefix(frame.as_slice(), 100).unwrap(), b"abcdef");
+
+        let compressed = compress(b"compressed compressed compressed compressed");
+        let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+        assert_eq!(reader.decode_block_ex(&mut Vec::new(), &[]).unwrap(), BlockOutcome::Data { len: 43, stored: false });
+    }
+
+    #[test]
+    fn block_checksum_spanning_chunks() {
+        let noise = noise(100_000);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_checksums(true).compress(noise.as_slice(), &mut compressed).unwrap();
+        assert_eq!(decompress_frame(compressed.as_slice()).unwrap(), noise);
+
+        // the block is stored, so this flips a bit in the output (and in the last chunk we hash)
+        compressed[90_000] ^= 1;
+        let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+        let mut output = Vec::new();
+        assert!(matches!(reader.decode_block(&mut output, &[]), Err(DecompressionError::BlockChecksumFail)));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "You must pass an empty buffer"))]
+    fn non_empty_output() {
+        let compressed = compress(b"some data");
+        let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+        let mut output = b"leftovers".to_vec();
+        assert!(matches!(reader.decode_block(&mut output, &[]), Err(DecompressionError::NonEmptyOutputBuffer)));
+        assert_eq!(output, b"leftovers");
+
+        // nothing happened, so we can carry on
+        output.clear();
+        reader.decode_block(&mut output, &[]).unwrap();
+        assert_eq!(output, b"some data");
+    }
+
+    #[test]
+    fn late_dictionary() {
+        let dictionary = b"a dictionary that we look up by its id";
+        let plaintext = b"the plaintext looks up a dictionary by its id, the dictionary that we look up";
+        let mut compressed = Vec::new();
+        CompressionSettings::default().dictionary(7, dictionary).compress(&plaintext[..], &mut compressed).unwrap();
+
+        let reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+        assert_eq!(reader.dictionary_id(), Some(7));
+        let mut output = Vec::new();
+        reader.with_dictionary(dictionary).unwrap().into_read().read_to_end(&mut output).unwrap();
+        assert_eq!(output, &plaintext[..]);
+
+        let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+        assert!(reader.decode_block(&mut Vec::new(), &[]).is_err());
+        assert!(matches!(reader.with_dictionary(dictionary), Err(DecompressionError::DictionaryAfterDecoding)));
+    }
+
+    #[test]
+    fn content_size_verification() {
+        let plaintext = b"twenty-six bytes of content";
+        for &(claimed, too_long) in &[(10, true), (100, false)] {
+            let mut compressed = Vec::new();
+            CompressionSettings::default().compress_with_size_unchecked(&plaintext[..], &mut compressed, claimed).unwrap();
+
+            let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+            let mut output = Vec::new();
+            if !too_long {
+                reader.decode_block(&mut output, &[]).unwrap();
+                output.clear();
+            }
+            assert!(matches!(reader.decode_block(&mut output, &[]),
+                Err(DecompressionError::ContentSizeMismatch { expected, actual: 27 }) if expected == claimed));
+
+            let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+            reader.ignore_content_size();
+            assert_eq!(reader.frame_size(), None);
+            assert_eq!(decode_all(reader).unwrap(), &plaintext[..]);
+
+            // works for lazy readers as well
+            let mut reader = LZ4FrameReader::new_lazy(compressed.as_slice());
+            reader.ignore_content_size();
+            assert_eq!(decode_all(reader).unwrap(), &plaintext[..]);
+        }
+    }
+
+    #[test]
+    fn skip_content_checksum() {
+        let plaintext = b"some content, some content, some content";
+        let mut compressed = Vec::new();
+        CompressionSettings::default().compress(&plaintext[..], &mut compressed).unwrap();
+        let len = compressed.len();
+        compressed[len - 1] ^= 1;
+        compressed.extend_from_slice(b"trailer");
+
+        let mut input = compressed.as_slice();
+        assert!(matches!(decode_all(LZ4FrameReader::new(&mut input).unwrap()), Err(DecompressionError::FrameChecksumFail)));
+
+        // the checksum is still consumed, so whatever follows the frame is where it should be
+        let mut input = compressed.as_slice();
+        let mut reader = LZ4FrameReader::new(&mut input).unwrap();
+        reader.skip_content_checksum(true);
+        assert_eq!(decode_all(reader).unwrap(), &plaintext[..]);
+        assert_eq!(input, b"trailer");
+
+        // changing our mind is fine as long as nothing was decoded yet
+        let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap();
+        reader.skip_content_checksum(true);
+        reader.skip_content_checksum(false);
+        assert!(matches!(decode_all(reader), Err(DecompressionError::FrameChecksumFail)));
+    }
+
+    #[test]
+    fn read_spans_blocks() {
+        let plaintext: Vec<u8> = compressible_changing_every(300_000, 7000);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).block_checksums(true)
+            .compress(plaintext.as_slice(), &mut compressed).unwrap();
+
+        let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap().into_read();
+        let mut buf = vec![0; 1 << 20];
+        assert_eq!(reader.read(&mut buf).unwrap(), plaintext.len());
+        assert!(buf[..plaintext.len()] == plaintext[..]);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        // corrupt the checksum of the third block: we get the first two blocks, then the error
+        let block_length = |offset: usize| (u32::from_le_bytes(compressed[offset..][..4].try_into().unwrap()) & 0x7FFF_FFFF) as usize;
+        let mut offset = 7;
+        for _ in 0..2 {
+            offset += 4 + block_length(offset) + 4;
+        }
+        let checksum_offset = offset + 4 + block_length(offset);
+        compressed[checksum_offset] ^= 1;
+
+        let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap().into_read();
+        assert_eq!(reader.read(&mut buf).unwrap(), 2 * 64 * 1024);
+        assert!(reader.read(&mut buf).is_err());
+    }
+
+    #[test]
+    fn read_to_end_appends() {
+        let plaintext: Vec<u8> = compressible_changing_every(300_000, 7000);
+        for &independent in &[true, false] {
+            let mut compressed = Vec::new();
+            let mut settings = CompressionSettings::default();
+            settings.block_size(64 * 1024);
+            if !independent {
+                settings.linked_blocks();
+            }
+            settings.compress_with_size(io::Cursor::new(&plaintext), &mut compressed).unwrap();
+
+            let mut reader = LZ4FrameReader::new(compressed.as_slice()).unwrap().into_read();
+            let mut first = [0; 1000];
+            reader.read_exact(&mut first).unwrap();
+            let mut output = b"existing data".to_vec();
+            assert_eq!(reader.read_to_end(&mut output).unwrap(), plaintext.len() - 1000);
+            assert!(output[..13] == b"existing data"[..] && output[13..] == plaintext[1000..]);
+        }
+
+        // errors leave only the blocks that were decoded successfully
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).compress(plaintext.as_slice(), &mut compressed).unwrap();
+        compressed.truncate(compressed.len() - 100);
+        let mut output = Vec::new();
+        assert!(LZ4FrameReader::new(compressed.as_slice()).unwrap().into_read().read_to_end(&mut output).is_err());
+        assert_eq!(output.len() % (64 * 1024), 0);
+        assert!(output[..] == plaintext[..output.len()]);
+    }
+
+    #[test]
+    fn reserved_header_bits() {
+        let plaintext = b"reserved bits are for future use".repeat(10);
+        let mut lenient = DecompressionSettings::default();
+        lenient.reserved_bits(ReservedBits::Lenient);
+
+        for &(index, mask) in &[(4, 0x02), (5, 0x80), (5, 0x08), (5, 0x04), (5, 0x02), (5, 0x01)] {
+            let mut frame = compress(&plaintext);
+            toggle_header_bits(&mut frame, index, mask);
+
+            let strict = LZ4FrameReader::new(frame.as_slice()).err().unwrap();
+            match (index, strict) {
+                (4, DecompressionError::HeaderParseError(ParseError::ReservedFlagBitsSet(bits))) => assert_eq!(bits, mask),
+                (5, DecompressionError::HeaderParseError(ParseError::ReservedBdBitsSet(bits))) => assert_eq!(bits, mask),
+                (_, e) => panic!("unexpected error for bits {:#x} in byte {}: {:?}", mask, index, e),
+            }
+            assert!(LZ4FrameReader::new_with_settings(frame.as_slice(), DecompressionSettings::default()).is_err());
+
+            let reader = LZ4FrameReader::new_with_settings(frame.as_slice(), lenient).unwrap();
+            assert_eq!(reader.block_size(), Some(4 * 1024 * 1024));
+            assert_eq!(decode_all(reader).unwrap(), plaintext);
+            assert_eq!(decode_all(LZ4FrameReader::new_lenient(frame.as_slice()).unwrap()).unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn restart() {
+        let plaintext: Vec<u8> = compressible(200_000);
+        let mut data = b"junk before the frame".to_vec();
+        CompressionSettings::default().block_size(64 * 1024).content_checksum(true).compress(plaintext.as_slice(), &mut data).unwrap();
+        let mut cursor = io::Cursor::new(data);
+        cursor.set_position(21);
+
+        assert_eq!(LZ4FrameReader::new(cursor.clone()).unwrap().restart().unwrap_err().kind(), io::ErrorKind::Unsupported);
+
+        let mut reader = LZ4FrameReader::new_seekable(cursor).unwrap();
+        let mut output = Vec::new();
+        reader.decode_block(&mut output, &[]).unwrap();
+        assert_eq!(output, &plaintext[..64 * 1024]);
+
+        for _ in 0..2 {
+            reader.restart().unwrap();
+            assert_eq!(reader.decompressed_bytes_written(), 0);
+            let mut output = Vec::new();
+            let mut block = Vec::new();
+            while let BlockOutcome::Data { .. } = reader.decode_block_ex(&mut block, &[]).unwrap() {
+                output.append(&mut block);
+            }
+            assert!(output == plaintext);
+        }
+    }
+
+    #[test]
+    fn unsupported_version() {
+        for &version in &[0b00, 0b10, 0b11] {
+            let mut frame = compress(b"version check");
+            // also set the reserved flag bit, the version must be reported first anyway
+            toggle_header_bits(&mut frame, 4, (version ^ 0b01) << 6 | 0b10);
+            match LZ4FrameReader::new(frame.as_slice()) {
+                Err(DecompressionError::UnsupportedVersion(v)) => assert_eq!(v, version),
+                other => panic!("version {}: {:?}", version, other.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn header_checksum() {
+        // the spec has no worked example, so these frames come from the reference CLI:
+        // an empty frame (`lz4 -c`) and "hello" with a content size (`lz4 --content-size -B4`)
+        let empty = [0x04, 0x22, 0x4d, 0x18, 0x64, 0x40, 0xa7, 0x00, 0x00, 0x00, 0x00, 0x05, 0x5d, 0xcc, 0x02];
+        let hello = [0x04, 0x22, 0x4d, 0x18, 0x6c, 0x40, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2c,
+            0x05, 0x00, 0x00, 0x80, b'h', b'e', b'l', b'l', b'o', 0x00, 0x00, 0x00, 0x00, 0xf9, 0x77, 0x00, 0xfb];
+        assert_eq!(decompress_frame(&empty[..]).unwrap(), b"");
+        assert_eq!(decompress_frame(&hello[..]).unwrap(), b"hello");
+
+        for &(frame, checksum_pos, checksum, header_len) in &[(&empty[..], 6, 0xa7, 2), (&hello[..], 14, 0x2c, 10)] {
+            let mut frame = frame.to_vec();
+            frame[checksum_pos] = 0x42;
+            match LZ4FrameReader::new(frame.as_slice()) {
+                Err(DecompressionError::HeaderChecksumFail { expected, actual, header_len: len }) => {
+                    assert_eq!((expected, actual, len), (0x42, checksum, header_len));
+                }
+                other => panic!("{:?}", other.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn lenient() {
+        let plaintext = b"lenient readers only complain about checksums";
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_checksums(true).compress(&plaintext[..], &mut compressed).unwrap();
+        // header checksum, block checksum (before the end mark) and content checksum
+        let len = compressed.len();
+        for &pos in &[6, len - 9, len - 1] {
+            compressed[pos] ^= 1;
+        }
+        assert!(matches!(LZ4FrameReader::new(compressed.as_slice()), Err(DecompressionError::HeaderChecksumFail { .. })));
+
+        let mut reader = LZ4FrameReader::new_lenient(compressed.as_slice()).unwrap();
+        let mut output = Vec::new();
+        reader.decode_block(&mut output, &[]).unwrap();
+        assert_eq!(output, plaintext);
+        assert_eq!(reader.decode_block_ex(&mut Vec::new(), &[]).unwrap(), BlockOutcome::EndOfFrame { checksum_verified: false });
+        // asking again doesn't pretend the checksum was verified after all
+        assert_eq!(reader.decode_block_ex(&mut Vec::new(), &[]).unwrap(), BlockOutcome::EndOfFrame { checksum_verified: false });
+
+        // other errors still are errors
+        compressed.truncate(len - 2);
+        let mut output = Vec::new();
+        assert!(LZ4FrameReader::new_lenient(compressed.as_slice()).unwrap().into_read().read_to_end(&mut output).is_err());
+    }
+
+    #[test]
+    fn report() {
+        // a compressible block, a stored block of noise and a short compressible one
+        let mut plaintext = b"compressible ".repeat(5042)[..64 * 1024].to_vec();
+        plaintext.extend(noise(64 * 1024));
+        plaintext.extend_from_slice(&b"compressible ".repeat(100));
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).block_checksums(true).compress(plaintext.as_slice(), &mut compressed).unwrap();
+
+        let (output, report) = decompress_frame_with_report(compressed.as_slice()).unwrap();
+        assert!(output == plaintext);
+        assert_eq!(report.frame_info.block_size(), 64 * 1024);
+        assert_eq!(report.compressed_bytes, compressed.len() as u64);
+        assert_eq!(report.decompressed_bytes, plaintext.len() as u64);
+        assert!(report.content_checksum);
+
+        let sizes: Vec<_> = report.blocks.iter().map(|b| (b.decompressed_size, b.stored, b.checksum)).collect();
+        assert_eq!(sizes, [(64 * 1024, false, true), (64 * 1024, true, true), (1300, false, true)]);
+        assert_eq!(report.blocks[1].compressed_size, 64 * 1024);
+        // header, end mark and content checksum plus length field and checksum of each block
+        let overhead = 7 + 4 + 4 + 3 * 8;
+        assert_eq!(report.blocks.iter().map(|b| b.compressed_size).sum::<usize>() + overhead, compressed.len());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel() {
+        use super::parallel_decompress;
+        use crate::framed::LZ4FrameWriter;
+
+        let mut noise = Noise::default();
+        let plaintext: Vec<u8> = (0..1_000_000u32).map(|i| if i / 100_000 % 3 == 0 {
+            noise.byte() // some stored blocks
+        } else {
+            compressible_byte(i, 1000)
+        }).collect();
+        let mut compressed = b"before".to_vec();
+        CompressionSettings::default().block_size(64 * 1024).block_checksums(true)
+            .compress_with_size(io::Cursor::new(&plaintext), &mut compressed).unwrap();
+        compressed.extend_from_slice(b"after");
+        let decompress = |compressed: &[u8], threads| {
+            let mut input = io::Cursor::new(compressed);
+            input.set_position(6);
+            let mut output = io::Cursor::new(b"prefix".to_vec());
+            output.set_position(6);
+            let result = parallel_decompress(&mut input, &mut output, threads);
+            result.map(|len| {
+                assert_eq!(output.position(), 6 + len);
+                assert_eq!(&compressed[input.position() as usize..], b"after");
+                assert_eq!(&output.get_ref()[..6], b"prefix");
+                output.into_inner().split_off(6)
+            })
+        };
+
+        for &threads in &[0, 1, 3, 8] {
+            assert!(decompress(&compressed, threads).unwrap() == plaintext);
+        }
+
+        let mut corrupted = compressed.clone();
+        let len = corrupted.len();
+        corrupted[len - 6] ^= 1;
+        assert!(matches!(decompress(&corrupted, 4), Err(DecompressionError::FrameChecksumFail)));
+        corrupted[len / 2] ^= 1;
+        assert!(matches!(decompress(&corrupted, 4), Err(DecompressionError::BlockChecksumFail)));
+        assert!(matches!(decompress(&compressed[..len / 2], 4), Err(DecompressionError::InputError(_))));
+
+        // no content size, and a block shorter than the block size in the middle
+        let mut compressed = b"before".to_vec();
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024);
+        let mut writer = LZ4FrameWriter::new(&mut compressed, &settings).unwrap();
+        writer.write_all(&plaintext[..100_000]).unwrap();
+        writer.flush().unwrap();
+        writer.write_all(&plaintext[100_000..]).unwrap();
+        writer.finish().unwrap();
+        compressed.extend_from_slice(b"after");
+        assert!(decompress(&compressed, 3).unwrap() == plaintext);
+
+        let mut linked = b"before".to_vec();
+        CompressionSettings::default().linked_blocks().compress(plaintext.as_slice(), &mut linked).unwrap();
+        assert!(matches!(decompress(&linked, 4), Err(DecompressionError::LinkedBlocks)));
+        let mut with_dictionary = b"before".to_vec();
+        CompressionSettings::default().dictionary(1, b"dict").compress(plaintext.as_slice(), &mut with_dictionary).unwrap();
+        assert!(matches!(decompress(&with_dictionary, 4), Err(DecompressionError::DictionaryNotSupported(1))));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn to_file_parallel() {
+        use std::hash::Hasher;
+        use twox_hash::XxHash32;
+        use super::decompress_to_file_parallel;
+
+        let plaintext: Vec<u8> = compressible(300_000);
+        let check = |compressed: &[u8]| {
+            let file = tempfile::NamedTempFile::new().unwrap();
+            assert_eq!(decompress_to_file_parallel(compressed, file.path(), 3).unwrap(), plaintext.len() as u64);
+            assert!(std::fs::read(file.path()).unwrap() == plaintext);
+        };
+
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).block_checksums(true)
+            .compress_with_size(io::Cursor::new(&plaintext), &mut compressed).unwrap();
+        check(&compressed);
+        let len = compressed.len();
+        compressed[len - 1] ^= 1;
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert!(matches!(decompress_to_file_parallel(compressed.as_slice(), file.path(), 3), Err(DecompressionError::FrameChecksumFail)));
+
+        // no content size, so this takes the sequential path
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).compress(plaintext.as_slice(), &mut compressed).unwrap();
+        check(&compressed);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).linked_blocks().compress(plaintext.as_slice(), &mut compressed).unwrap();
+        check(&compressed);
+
+        // a short block in the middle moves all blocks after it
+        let mut writer = CompressionSettings::default().block_size(64 * 1024).content_checksum(false).streaming_compress(Vec::new()).unwrap();
+        writer.write_all(&plaintext[..1000]).unwrap();
+        writer.flush().unwrap();
+        writer.write_all(&plaintext[1000..]).unwrap();
+        let streamed = writer.finish().unwrap();
+        // version 1, independent blocks, content size; 64K blocks
+        let mut descriptor = vec![0x68, 0x40];
+        descriptor.extend_from_slice(&(plaintext.len() as u64).to_le_bytes());
+        let mut hasher = XxHash32::with_seed(0);
+        hasher.write(&descriptor);
+        let mut compressed = 0x184D2204u32.to_le_bytes().to_vec();
+        compressed.extend_from_slice(&descriptor);
+        compressed.push((hasher.finish() >> 8) as u8);
+        compressed.extend_from_slice(&streamed[7..]);
+        assert_eq!(decompress_frame(compressed.as_slice()).unwrap(), plaintext);
+        check(&compressed);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn to_file_parallel_declared_size() {
+        use std::hash::Hasher;
+        use twox_hash::XxHash32;
+        use super::{decompress_to_file_parallel, decompress_to_file_parallel_with_limits};
+
+        // an empty frame that claims to be a TiB long
+        let mut descriptor = vec![0x68, 0x40];
+        descriptor.extend_from_slice(&(1u64 << 40).to_le_bytes());
+        let mut hasher = XxHash32::with_seed(0);
+        hasher.write(&descriptor);
+        let mut hostile = 0x184D2204u32.to_le_bytes().to_vec();
+        hostile.extend_from_slice(&descriptor);
+        hostile.push((hasher.finish() >> 8) as u8);
+        hostile.extend_from_slice(&[0; 4]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out");
+        let result = decompress_to_file_parallel_with_limits(hostile.as_slice(), &path, 3, *DecompressionLimits::default().max_output(1 << 20));
+        assert!(matches!(result, Err(DecompressionError::OutputLimitExceeded)), "{:?}", result);
+        assert!(!path.exists());
+
+        // without a limit, the file is resized (if the file system allows it), but not for long
+        assert!(decompress_to_file_parallel(hostile.as_slice(), &path, 3).is_err());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+        // a corrupt block in the middle: the file ends with the blocks before it
+        let plaintext = compressible(300_000);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).block_checksums(true)
+            .compress_with_size(io::Cursor::new(&plaintext), &mut compressed).unwrap();
+        let len = compressed.len();
+        compressed[len / 2] ^= 1;
+        assert!(decompress_to_file_parallel(compressed.as_slice(), &path, 3).is_err());
+        let written = std::fs::read(&path).unwrap();
+        assert!(written.len() < plaintext.len() && written.len() % (64 * 1024) == 0, "{}", written.len());
+        assert!(plaintext.starts_with(&written));
+
+        let limits = *DecompressionLimits::default().max_output(100_000);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).compress(plaintext.as_slice(), &mut compressed).unwrap();
+        let result = decompress_to_file_parallel_with_limits(compressed.as_slice(), &path, 3, limits);
+        assert!(matches!(result, Err(DecompressionError::OutputLimitExceeded)), "{:?}", result);
+        assert_eq!(std::fs::read(&path).unwrap(), &plaintext[..64 * 1024]);
+    }
+
+    #[test]
+    fn raw_blocks() {
+        let mut plaintext = compressible(100_000);
+        plaintext.extend(noise(50_000));
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).block_checksums(true).compress(plaintext.as_slice(), &mut compressed).unwrap();
+        let first_len = compressed.len();
+        compressed.extend(compress(b"second frame"));
+
+        let mut reader = compressed.as_slice();
+        let mut frame_reader = LZ4FrameReader::new(&mut reader).unwrap();
+        let blocks: Vec<_> = frame_reader.raw_blocks().collect::<Result<_, _>>().unwrap();
+        assert!(frame_reader.raw_blocks().next().is_none());
+        assert_eq!(frame_reader.compressed_bytes_read(), first_len as u64);
+        assert_eq!(blocks.iter().map(|b| (b.index, b.stored, b.checksum.is_some())).collect::<Vec<_>>(),
+            [(0, false, true), (1, false, true), (2, true, true)]);
+        let mut decoded = Vec::new();
+        for block in &blocks {
+            if block.stored {
+                decoded.extend_from_slice(&block.data);
+            } else {
+                decoded.extend(decompress_single_block(&block.data, &[], 64 * 1024).unwrap());
+            }
+        }
+        assert!(decoded == plaintext);
+        assert_eq!(decompress_frame(reader).unwrap(), b"second frame");
+
+        let mut corrupted = compressed.clone();
+        corrupted[20] ^= 1;
+        let mut frame_reader = LZ4FrameReader::new(corrupted.as_slice()).unwrap();
+        assert!(matches!(frame_reader.raw_blocks().next(), Some(Err(DecompressionError::BlockChecksumFail))));
+    }
+
+    #[test]
+    fn dictionary_provider() {
+        let mut dictionaries = std::collections::HashMap::new();
+        dictionaries.insert(1, b"the first dictionary, the first dictionary".to_vec());
+        dictionaries.insert(2, b"the second dictionary, the second dictionary".to_vec());
+        let frame = |id, dict: &[u8], plaintext: &[u8]| {
+            let mut compressed = Vec::new();
+            CompressionSettings::default().dictionary(id, dict).compress(plaintext, &mut compressed).unwrap();
+            compressed
+        };
+        let read = |compressed: &[u8], provider: &dyn DictionaryProvider| {
+            let mut output = Vec::new();
+            LZ4FrameReader::with_dictionary_provider(compressed, provider)?.into_read().read_to_end(&mut output)?;
+            Ok::<_, DecompressionError>(output)
+        };
+
+        for (&id, dict) in &dictionaries {
+            // mostly references to the dictionary, so this can't be decoded without it
+            let plaintext = [&dict[..], b"!"].concat();
+            let compressed = frame(id, dict, &plaintext);
+            assert!(decompress_frame(compressed.as_slice()).is_err());
+            assert_eq!(read(&compressed, &dictionaries).unwrap(), plaintext);
+        }
+
+        let unknown = frame(3, &dictionaries[&1], b"whatever");
+        assert!(matches!(read(&unknown, &dictionaries), Err(DecompressionError::DictionaryRequired(3))));
+        assert_eq!(read(&compress(b"no dictionary"), &dictionaries).unwrap(), b"no dictionary");
+    }
+
+    #[test]
+    fn decoder_dictionary() {
+        let dict = b"a dictionary shared by all threads, a dictionary shared by all threads";
+        let mut settings = CompressionSettings::default();
+        settings.dictionary(5, dict);
+        let shared = settings.decoder_dictionary().unwrap();
+        assert_eq!((shared.id(), shared.as_ref()), (5, &dict[..]));
+        assert!(CompressionSettings::default().decoder_dictionary().is_none());
+
+        let frames: Vec<_> = (0..8).map(|i| {
+            let mut compressed = Vec::new();
+            settings.compress(format!("{} {}", i, std::str::from_utf8(dict).unwrap()).as_bytes(), &mut compressed).unwrap();
+            compressed
+        }).collect();
+        thread::scope(|scope| {
+            for (i, frame) in frames.iter().enumerate() {
+                let shared = shared.clone();
+                scope.spawn(move || {
+                    let expected = format!("{} {}", i, std::str::from_utf8(dict).unwrap());
+                    let mut output = Vec::new();
+                    let reader = LZ4FrameReader::new(frame.as_slice()).unwrap().with_decoder_dictionary(shared).unwrap();
+                    reader.into_read().read_to_end(&mut output).unwrap();
+                    assert_eq!(output, expected.as_bytes());
+                });
+            }
+        });
+
+        // as a provider, alone or as one of several
+        let other = DecoderDictionary::new(6, b"some other dictionary");
+        let mut output = Vec::new();
+        LZ4FrameReader::with_dictionary_provider(frames[0].as_slice(), &shared).unwrap().into_read().read_to_end(&mut output).unwrap();
+        let dictionaries = [other.clone(), shared];
+        let mut output2 = Vec::new();
+        LZ4FrameReader::with_dictionary_provider(frames[0].as_slice(), &dictionaries[..]).unwrap().into_read().read_to_end(&mut output2).unwrap();
+        assert_eq!(output, output2);
+        assert!(matches!(LZ4FrameReader::with_dictionary_provider(frames[0].as_slice(), &other), Err(DecompressionError::DictionaryRequired(5))));
+        assert!(matches!(LZ4FrameReader::new(frames[0].as_slice()).unwrap().with_decoder_dictionary(other),
+                         Err(DecompressionError::DictionaryRequired(5))));
+    }
+
+    /// Accepts at most 1000 bytes per call and fails after `capacity` bytes.
+    struct SlowWriter {
+        data: Vec<u8>,
+        capacity: usize,
+    }
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.data.len() >= self.capacity {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "sink is full"));
+            }
+            let len = buf.len().min(1000).min(self.capacity - self.data.len());
+            self.data.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn to_writer() {
+        let plaintext: Vec<u8> = compressible(200_000);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).compress(plaintext.as_slice(), &mut compressed).unwrap();
+
+        let mut writer = SlowWriter { data: Vec::new(), capacity: usize::MAX };
+        assert_eq!(decompress_frame_to_writer(compressed.as_slice(), &mut writer).unwrap(), plaintext.len() as u64);
+        assert!(writer.data == plaintext);
+
+        let mut writer = SlowWriter { data: Vec::new(), capacity: 100_000 };
+        match decompress_frame_to_writer(compressed.as_slice(), &mut writer) {
+            Err(DecompressionError::OutputError(e)) => assert_eq!(e.to_string(), "sink is full"),
+            other => panic!("{:?}", other),
+        }
+        assert!(writer.data == plaintext[..100_000]);
+    }
+
+    #[test]
+    fn settings() {
+        let dictionary = b"settings for the reader, the counterpart to the settings for the writer";
+        let plaintext = b"the reader settings mirror the writer settings".repeat(100);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().dictionary(7, dictionary).block_checksums(true)
+            .compress(plaintext.as_slice(), &mut compressed).unwrap();
+
+        let mut output = Vec::new();
+        let written = DecompressionSettings::default().dictionary(dictionary).decompress(compressed.as_slice(), &mut output).unwrap();
+        assert_eq!(written, plaintext.len() as u64);
+        assert_eq!(output, plaintext);
+
+        let mut limits = DecompressionLimits::default();
+        limits.max_output(1000);
+        let reader = DecompressionSettings::default().dictionary(dictionary).limits(limits).frame_reader(compressed.as_slice()).unwrap();
+        assert!(matches!(decode_all(reader), Err(DecompressionError::OutputLimitExceeded)));
+
+        // corrupt both checksums (the content checksum is last, the block checksum right before the end mark)
+        let len = compressed.len();
+        compressed[len - 1] ^= 1;
+        compressed[len - 9] ^= 1;
+        let mut settings = DecompressionSettings::default();
+        settings.dictionary(dictionary);
+        assert!(matches!(decode_all(settings.frame_reader(compressed.as_slice()).unwrap()), Err(DecompressionError::BlockChecksumFail)));
+        let mut reader = settings.verify_checksums(false).frame_reader(compressed.as_slice()).unwrap();
+        let mut output = Vec::new();
+        assert!(matches!(reader.decode_block_ex(&mut output, &[]).unwrap(), BlockOutcome::Data { .. }));
+        assert_eq!(output, plaintext);
+        assert_eq!(reader.decode_block_ex(&mut Vec::new(), &[]).unwrap(), BlockOutcome::EndOfFrame { checksum_verified: false });
+        // asking again doesn't pretend the checksum was verified after all
+        assert_eq!(reader.decode_block_ex(&mut Vec::new(), &[]).unwrap(), BlockOutcome::EndOfFrame { checksum_verified: false });
+    }
+
+    #[test]
+    fn decompressor_reuses_buffers() {
+        let mut decompressor = Decompressor::new();
+        let mut output = Vec::new();
+        let mut expected = Vec::new();
+        for i in 0..10 {
+            let plaintext = format!("frame {} of many small frames, frame {} of many", i, i).repeat(i + 1).into_bytes();
+            let mut settings = CompressionSettings::default();
+            if i % 2 == 0 {
+                settings.linked_blocks();
+            }
+            let mut compressed = Vec::new();
+            settings.compress(plaintext.as_slice(), &mut compressed).unwrap();
+
+            let info = decompressor.decompress_into(io::Cursor::new(compressed), &mut output).unwrap();
+            assert_eq!(info.independent_blocks(), i % 2 != 0);
+            expected.extend_from_slice(&plaintext);
+            assert!(output == expected);
+        }
+        let read_buf = decompressor.read_buf.as_ptr();
+        decompressor.decompress_into(&compress(b"one more")[..], &mut output).unwrap();
+        assert_eq!(decompressor.read_buf.as_ptr(), read_buf);
+
+        // cut into the block itself, not just the checksum after it
+        let mut truncated = compress(b"truncated");
+        truncated.truncate(truncated.len() - 10);
+        let before = output.len();
+        assert!(matches!(decompressor.decompress_into(truncated.as_slice(), &mut output), Err(DecompressionError::InputError(_))));
+        assert_eq!(output.len(), before);
+    }
+
+    /// Returns a single byte per `read`, with an `Interrupted` error before each one.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        interrupt: bool,
+    }
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            let len = std::cmp::min(1, buf.len());
+            self.data.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn short_reads() {
+        let plaintext: Vec<u8> = compressible(150_000);
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).block_checksums(true).linked_blocks();
+        let mut compressed = Vec::new();
+        settings.compress(Trickle { data: &plaintext, interrupt: false }, &mut compressed).unwrap();
+        compressed.extend_from_slice(b"after the frame");
+
+        let mut reader = Trickle { data: &compressed, interrupt: false };
+        assert_eq!(decompress_frame(&mut reader).unwrap(), plaintext);
+        assert_eq!(reader.data, b"after the frame");
+
+        let mut reader = Trickle { data: &compressed[..compressed.len() - 20], interrupt: false };
+        assert!(decompress_frame(&mut reader).is_err());
+    }
+
+    #[test]
+    fn to_file() {
+        let plaintext = b"straight into a file".repeat(1000);
+        let compressed = compress(&plaintext);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plain");
+        std::fs::write(&path, b"previous contents that are a lot longer than the plaintext".repeat(1000)).unwrap();
+        assert_eq!(decompress_to_file(compressed.as_slice(), &path).unwrap(), plaintext.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), plaintext);
+
+        assert!(matches!(decompress_to_file(compressed.as_slice(), dir.path().join("missing/plain")), Err(DecompressionError::OutputError(_))));
+    }
+
+    #[test]
+    fn verify_header_only() {
+        let mut frame = Vec::new();
+        CompressionSettings::default().dictionary(5, b"dict").compress_with_size(io::Cursor::new(b"header only"), &mut frame).unwrap();
+        let header_len = 4 + 2 + 8 + 4 + 1;
+
+        verify_header_checksum(&frame).unwrap();
+        verify_header_checksum(&frame[..header_len]).unwrap();
+        let info = FrameInfo::parse(&frame[..header_len]).unwrap();
+        assert_eq!((info.frame_size(), info.dictionary_id()), (Some(11), Some(5)));
+
+        assert!(matches!(verify_header_checksum(&frame[..header_len - 1]), Err(DecompressionError::InputError(_))));
+        assert!(matches!(verify_header_checksum(b"LZ4?"), Err(DecompressionError::WrongMagic(_))));
+        frame[10] ^= 1;
+        assert!(matches!(verify_header_checksum(&frame), Err(DecompressionError::HeaderChecksumFail { header_len: 14, .. })));
+        assert!(matches!(FrameInfo::parse(frame.as_slice()), Err(DecompressionError::HeaderChecksumFail { .. })));
+
+        // only the checksum matters, not whether we could decode the frame
+        let mut frame = compress(b"reserved");
+        toggle_header_bits(&mut frame, 5, 0x01);
+        verify_header_checksum(&frame).unwrap();
+        assert!(FrameInfo::parse(frame.as_slice()).is_err());
+    }
+
+    #[test]
+    fn pre_parsed_header() {
+        let plaintext: Vec<u8> = compressible(200_000);
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).compress(plaintext.as_slice(), &mut compressed).unwrap();
+
+        let mut input = compressed.as_slice();
+        let info = FrameInfo::parse(&mut input).unwrap();
+        let mut reader = LZ4FrameReader::new_with_frame_info(input, info.clone()).unwrap();
+        assert_eq!(reader.block_size(), Some(64 * 1024));
+        assert_eq!(reader.compressed_bytes_read(), 0);
+        let mut output = Vec::new();
+        while let BlockOutcome::Data { .. } = reader.decode_block_into(&mut output, &[]).unwrap() {}
+        assert_eq!(output, plaintext);
+        assert_eq!(reader.compressed_bytes_read(), input.len() as u64);
+
+        // the same header, built by hand
+        let mut built = FrameInfo::new(64 * 1024);
+        built.set_independent_blocks(true).set_content_checksum(true);
+        let reader = LZ4FrameReader::new_with_frame_info(input, built).unwrap();
+        assert_eq!(decode_all(reader).unwrap(), plaintext);
+
+        // the header is not read again, so this only works with the reader positioned after it
+        let reader = LZ4FrameReader::new_with_frame_info(compressed.as_slice(), info).unwrap();
+        assert!(decode_all(reader).is_err());
+    }
+
+    #[test]
+    fn resync() {
+        let first: Vec<u8> = compressible(100_000);
+        let second = b"the second frame survives".repeat(100);
+        let mut archive = compress(&first);
+        let damaged = archive.len() / 2;
+        archive[damaged] ^= 0xff;
+        // garbage with a magic number and a broken header in it
+        archive.extend_from_slice(&[1, 2, 0x04, 0x22, 0x4D, 0x18, 0x64, 0x40, 0x00, 3]);
+        let second_start = archive.len() as u64;
+        archive.extend(compress(&second));
+
+        // a plain Read that can't seek back
+        let mut input = archive.as_slice();
+        assert!(decompress_frame(&mut input).is_err());
+        let position = (archive.len() - input.len()) as u64;
+        let (skipped, mut rest) = resync_to_next_frame(&mut input).unwrap().unwrap();
+        assert_eq!(skipped, second_start - position);
+        assert_eq!(decompress_frame(&mut rest).unwrap(), second);
+        assert!(input.is_empty());
+
+        // already at a frame, or at a skippable frame
+        let (skipped, rest) = resync_to_next_frame(&archive[second_start as usize..]).unwrap().unwrap();
+        assert_eq!(skipped, 0);
+        assert_eq!(decompress_frame(rest).unwrap(), second);
+        let mut skippable = vec![0xff; 100_000];
+        skippable.extend_from_slice(&[0x5A, 0x2A, 0x4D, 0x18, 0, 0, 0, 0]);
+        let (skipped, mut rest) = resync_to_next_frame(skippable.as_slice()).unwrap().unwrap();
+        assert_eq!(skipped, 100_000);
+        let mut frame = Vec::new();
+        rest.read_to_end(&mut frame).unwrap();
+        assert_eq!(frame, &skippable[100_000..]);
+
+        // nothing left
+        assert!(resync_to_next_frame(&archive[damaged..second_start as usize]).unwrap().is_none());
+    }
+
+    #[test]
+    fn maybe_compressed() {
+        let plaintext = b"maybe compressed, maybe not, maybe compressed, maybe not".to_vec();
+        let read_all = |input: &[u8]| {
+            let mut reader = MaybeCompressedReader::new(input).unwrap();
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).unwrap();
+            (reader.was_compressed(), output)
+        };
+
+        let compressed = compress(&plaintext);
+        assert_eq!(read_all(&compressed), (true, plaintext.clone()));
+        assert_eq!(read_all(&plaintext), (false, plaintext.clone()));
+        assert_eq!(read_all(b"abc"), (false, b"abc".to_vec()));
+        assert_eq!(read_all(b""), (false, Vec::new()));
+        // right length, wrong magic
+        assert_eq!(read_all(&compressed[1..]), (false, compressed[1..].to_vec()));
+
+        let mut skippable = vec![0x5A, 0x2A, 0x4D, 0x18, 3, 0, 0, 0, 1, 2, 3];
+        skippable.extend_from_slice(&compressed);
+        assert_eq!(read_all(&skippable), (true, plaintext.clone()));
+        assert!(MaybeCompressedReader::new(&skippable[..11]).is_err());
+        assert!(MaybeCompressedReader::new(&skippable[..9]).is_err());
+
+        assert!(MaybeCompressedReader::new(&[0x02, 0x21, 0x4C, 0x18, 0, 0, 0, 0][..]).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use std::cmp;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use super::WINDOW_SIZE;
+
+
+/// Length of the substrings whose frequency we count.
+const KMER_LEN: usize = 8;
+/// The samples are cut into pieces of this size, which are the candidates for the dictionary.
+const SEGMENT_LEN: usize = 64;
+
+/// Build a dictionary (for `CompressionSettings::dictionary`) from samples of the data you are going to compress.
+///
+/// This is a basic heuristic, not an optimal trainer: it counts in how many samples each 8-byte substring appears
+/// and greedily picks the 64-byte pieces of the samples that cover the most common substrings not covered yet.
+/// The most valuable pieces end up at the end of the dictionary.
+///
+/// The result is at most `max_dict_size` bytes (and never more than 64 KiB, as LZ4 can't use more than that).
+/// It may be shorter, or even empty, if the samples don't have enough in common.
+pub fn train_dictionary(samples: &[&[u8]], max_dict_size: usize) -> Vec<u8> {
+    let max_dict_size = cmp::min(max_dict_size, WINDOW_SIZE);
+
+    // in how many samples each substring occurs; substrings occurring in a single sample are useless
+    let mut frequencies: HashMap<&[u8], u32> = HashMap::new();
+    let mut seen = HashSet::new();
+    for sample in samples {
+        seen.clear();
+        for kmer in sample.windows(KMER_LEN) {
+            if seen.insert(kmer) {
+                *frequencies.entry(kmer).or_insert(0) += 1;
+            }
+        }
+    }
+    frequencies.retain(|_, &mut count| count > 1);
+
+    let score = |segment: &[u8], frequencies: &HashMap<&[u8], u32>| -> u64 {
+        segment.windows(KMER_LEN).map(|kmer| u64::from(frequencies.get(kmer).copied().unwrap_or(0))).sum()
+    };
+
+    // lazy greedy selection: scores only ever go down, so a segment whose updated score
+    // is still at least as good as the next best stale score is the best one
+    let mut candidates: BinaryHeap<(u64, &[u8])> = samples.iter()
+        .flat_map(|sample| sample.chunks(SEGMENT_LEN))
+        .map(|segment| (score(segment, &frequencies), segment))
+        .filter(|&(score, _)| score > 0)
+        .collect();
+    let mut selected = Vec::new();
+    let mut dict_len = 0;
+    while let Some((stale_score, segment)) = candidates.pop() {
+        let current_score = score(segment, &frequencies);
+        if current_score == 0 {
+            continue;
+        }
+        if current_score < stale_score && matches!(candidates.peek(), Some(&(next, _)) if next > current_score) {
+            candidates.push((current_score, segment));
+            continue;
+        }
+        if dict_len + segment.len() > max_dict_size {
+            continue; // a shorter segment may still fit
+        }
+
+        for kmer in segment.windows(KMER_LEN) {
+            frequencies.remove(kmer);
+        }
+        dict_len += segment.len();
+        selected.push(segment);
+    }
+
+    let mut dict = Vec::with_capacity(dict_len);
+    for segment in selected.iter().rev() {
+        dict.extend_from_slice(segment);
+    }
+    dict
+}
+
+#[cfg(test)]
+mod tests {
+    use super::train_dictionary;
+    use crate::framed::CompressionSettings;
+
+    fn record(i: u32) -> Vec<u8> {
+        format!(r#"{{"id":{},"name":"user{}","email":"user{}@example.com","active":{},"roles":["reader","writer"]}}"#,
+            i, i * 7, i * 13, i % 3 == 1).into_bytes()
+    }
+
+    fn compressed_len(data: &[u8], dict: &[u8]) -> usize {
+        let mut output = Vec::new();
+        let mut settings = CompressionSettings::default();
+        if !dict.is_empty() {
+            settings.dictionary(0, dict);
+        }
+        settings.compress(data, &mut output).unwrap();
+        output.len()
+    }
+
+    #[test]
+    fn trained_dictionary_helps() {
+        let records: Vec<Vec<u8>> = (0..500).map(record).collect();
+        let samples: Vec<&[u8]> = records.iter().map(Vec::as_slice).collect();
+        let dict = train_dictionary(&samples, 1024);
+        assert!(!dict.is_empty() && dict.len() <= 1024);
+
+        let new_record = record(12345);
+        assert!(compressed_len(&new_record, &dict) < compressed_len(&new_record, &[]));
+    }
+
+    #[test]
+    fn nothing_in_common() {
+        assert!(train_dictionary(&[b"abcdefghijkl", b"mnopqrstuvwx"], 1024).is_empty());
+        assert!(train_dictionary(&[], 1024).is_empty());
+        assert!(train_dictionary(&[b"some text here", b"some text here"], 0).is_empty());
+    }
+}
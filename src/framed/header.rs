@@ -21,21 +21,35 @@ pub enum ParseError {
     UnimplementedBlocksize(u8),
     #[error("file version {0} not supported")]
     UnsupportedVersion(u8),
-    #[error("reserved bits in flags set")]
-    ReservedFlagBitsSet,
-    #[error("reserved bits in bd set")]
-    ReservedBdBitsSet,
+    #[error("reserved bits in flags set: {0:#010b}")]
+    ReservedFlagBitsSet(u8),
+    #[error("reserved bits in bd set: {0:#010b}")]
+    ReservedBdBitsSet(u8),
+}
+
+/// How to treat the header bits that the spec reserves for future use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReservedBits {
+    /// Reject frames that set any of them. This is what the reference decoder does.
+    #[default]
+    Strict,
+    /// Ignore them, for reading frames written by sloppy encoders.
+    Lenient,
 }
 
 impl Flags {
+    /// Parse the flag byte of a frame header.
+    ///
+    /// Any version other than 1 is always an error. The reserved bit is an error (`ReservedFlagBitsSet`)
+    /// with `ReservedBits::Strict` and ignored without a trace with `ReservedBits::Lenient`.
     #[throws(ParseError)]
-    pub fn parse(i: u8) -> Self {
+    pub fn parse(i: u8, reserved: ReservedBits) -> Self {
         let version = i >> 6;
         if version != 1 {
             throw!(ParseError::UnsupportedVersion(version));
         }
-        if (i & 0b10) != 0 {
-            throw!(ParseError::ReservedFlagBitsSet);
+        if reserved == ReservedBits::Strict && (i & 0b10) != 0 {
+            throw!(ParseError::ReservedFlagBitsSet(i & 0b10));
         }
 
         Flags::from_bits_truncate(i)
@@ -46,15 +60,21 @@ impl Flags {
     pub fn content_size(&self)       -> bool { self.contains(Flags::ContentSize) }
     pub fn content_checksum(&self)   -> bool { self.contains(Flags::ContentChecksum) }
     pub fn dictionary_id(&self)      -> bool { self.contains(Flags::DictionaryId) }
+
+    pub fn set_independent_blocks(&mut self, v: bool) { self.set(Flags::IndependentBlocks, v) }
+    pub fn set_block_checksums(&mut self, v: bool)    { self.set(Flags::BlockChecksums, v) }
+    pub fn set_content_size(&mut self, v: bool)       { self.set(Flags::ContentSize, v) }
+    pub fn set_content_checksum(&mut self, v: bool)   { self.set(Flags::ContentChecksum, v) }
+    pub fn set_dictionary_id(&mut self, v: bool)      { self.set(Flags::DictionaryId, v) }
 }
 
 pub struct BlockDescriptor(pub u8); // ??? or what else could "BD" stand for ???
 impl BlockDescriptor {
     pub fn new(block_maxsize: usize) -> Option<Self> {
         let maybe_maxsize = ((block_maxsize.trailing_zeros().saturating_sub(8)) / 2) as u8;
-        let bd = BlockDescriptor::parse(maybe_maxsize << 4).unwrap();
+        let bd = BlockDescriptor::parse(maybe_maxsize << 4, ReservedBits::Strict).ok()?;
         match bd.block_maxsize() {
-            Ok(x) if x == block_maxsize => (),
+            Ok(x) if x == block_maxsize as u64 => (),
             _ => return None,
         }
 
@@ -62,15 +82,17 @@ impl BlockDescriptor {
     }
 
     #[throws(ParseError)]
-    pub fn parse(i: u8) -> Self {
-        if (i & 0b10001111) != 0 {
-            throw!(ParseError::ReservedBdBitsSet);
+    pub fn parse(i: u8, reserved: ReservedBits) -> Self {
+        const RESERVED: u8 = 0b10001111;
+        if reserved == ReservedBits::Strict && (i & RESERVED) != 0 {
+            throw!(ParseError::ReservedBdBitsSet(i & RESERVED));
         }
-        BlockDescriptor(i)
+        BlockDescriptor(i & !RESERVED)
     }
 
+    /// This is a `u64` because the larger block sizes don't fit into a 16-bit `usize`.
     #[throws(ParseError)]
-    pub fn block_maxsize(&self) -> usize {
+    pub fn block_maxsize(&self) -> u64 {
         let size = (self.0 >> 4) & 0b111;
         if (4..8).contains(&size) {
             1 << (size * 2 + 8)
@@ -80,3 +102,21 @@ impl BlockDescriptor {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{Flags, ReservedBits};
+
+    #[test]
+    fn setters() {
+        let mut flags = Flags::empty();
+        flags.set_independent_blocks(true);
+        flags.set_content_checksum(true);
+        flags.set_dictionary_id(true);
+        flags.set_dictionary_id(false);
+        assert!(flags.independent_blocks() && flags.content_checksum());
+        assert!(!flags.block_checksums() && !flags.content_size() && !flags.dictionary_id());
+        assert_eq!(flags.bits(), 0b00100100);
+        assert_eq!(Flags::parse(0b01000000 | flags.bits(), ReservedBits::Strict).unwrap(), flags);
+    }
+}
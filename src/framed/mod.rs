@@ -10,16 +10,32 @@
 
 mod compress;
 mod decompress;
+mod dictionary;
 mod header;
+mod transcode;
 
 /// The four magic bytes at the start of every LZ4 frame (little endian).
 pub const MAGIC: u32 = 0x184D2204;
+/// Skippable frames have one of the 16 magic numbers matching this under `SKIPPABLE_MAGIC_MASK`.
+const SKIPPABLE_MAGIC: u32 = 0x184D2A50;
+const SKIPPABLE_MAGIC_MASK: u32 = 0xFFFFFFF0;
+/// The magic number of the legacy frame format, which we don't support.
+const LEGACY_MAGIC: u32 = 0x184C2102;
 /// The frame format sets the high bit of every length field to indicate that the data was not compressed.
 const INCOMPRESSIBLE: u32 = 1 << 31;
 /// The LZ4 raw format maintains a lookback window of exactly 64KiB.
 pub const WINDOW_SIZE: usize = 64 * 1024;
+/// Magic number of the skippable frame that carries the xxhash64 of the content.
+#[cfg(feature = "xxhash64")]
+const XXH64_FRAME_MAGIC: u32 = 0x184D2A5E;
+/// Skippable frames can contain anything, so this tells ours apart from other people's.
+#[cfg(feature = "xxhash64")]
+const XXH64_FRAME_TAG: [u8; 4] = *b"XH64";
 
 
 pub use compress::*;
 pub use decompress::*;
+pub use dictionary::train_dictionary;
+pub use header::ReservedBits;
+pub use transcode::{transcode_block_size, TranscodeError};
 
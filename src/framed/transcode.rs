@@ -0,0 +1,85 @@
+use std::io::{Read, Write};
+use thiserror::Error;
+use fehler::throws;
+
+use super::{CompressionError, CompressionSettings, DecompressionError, LZ4FrameReader, LZ4FrameWriter, BlockOutcome};
+
+
+/// Errors when transcoding an LZ4 frame.
+#[derive(Error, Debug)]
+pub enum TranscodeError {
+    #[error("error decompressing the input frame")]
+    Decompression(#[from] DecompressionError),
+    #[error("error compressing the output frame")]
+    Compression(#[from] CompressionError),
+}
+type Error = TranscodeError; // do it this way for better docs
+
+/// Read a frame from `reader` and write the same content to `writer` as a frame with blocks of `block_size`,
+/// returning the content size.
+///
+/// Everything else about the new frame comes from `settings` (whose block size is ignored). The content size is carried over
+/// from the input frame if it has one. If the input frame needs a dictionary, the one in `settings` is used to decode it.
+/// Only one block of each frame is in memory at a time.
+#[throws]
+pub fn transcode_block_size<R: Read, W: Write>(reader: R, writer: W, block_size: usize, settings: &CompressionSettings) -> u64 {
+    let mut frame_reader = LZ4FrameReader::new(reader)?;
+    if let Some(dictionary) = settings.decoder_dictionary() {
+        frame_reader = frame_reader.with_decoder_dictionary(dictionary)?;
+    }
+    let mut settings = settings.clone();
+    settings.block_size(block_size);
+    let mut frame_writer = LZ4FrameWriter::start(writer, &settings, frame_reader.frame_size())?;
+
+    let mut block = Vec::new();
+    loop {
+        block.clear();
+        if let BlockOutcome::EndOfFrame { .. } = frame_reader.decode_block_ex(&mut block, &[])? {
+            break;
+        }
+        frame_writer.push_all(&block)?;
+    }
+    frame_writer.finish()?;
+    frame_reader.decompressed_bytes_written()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::{transcode_block_size, TranscodeError};
+    use crate::framed::{CompressionError, CompressionSettings, LZ4FrameReader, decompress_frame};
+    use crate::test_data::compressible;
+
+    #[test]
+    fn transcode() {
+        let plaintext: Vec<u8> = compressible(300_000);
+        let dictionary = &plaintext[..1000];
+        let mut settings = CompressionSettings::default();
+        settings.dictionary(1, dictionary);
+        let mut compressed = Vec::new();
+        settings.compress_with_size(io::Cursor::new(&plaintext), &mut compressed).unwrap();
+
+        let mut transcoded = Vec::new();
+        assert_eq!(transcode_block_size(compressed.as_slice(), &mut transcoded, 64 * 1024, &settings).unwrap(), plaintext.len() as u64);
+        let frame_reader = LZ4FrameReader::new(transcoded.as_slice()).unwrap();
+        assert_eq!(frame_reader.block_size(), Some(64 * 1024));
+        assert_eq!(frame_reader.frame_size(), Some(plaintext.len() as u64));
+        assert_eq!(frame_reader.dictionary_id(), Some(1));
+        let mut output = Vec::new();
+        io::copy(&mut frame_reader.into_read_with_dictionary(dictionary), &mut output).unwrap();
+        assert!(output == plaintext);
+
+        // and back, to linked blocks without a content size
+        let mut compressed = Vec::new();
+        CompressionSettings::default().block_size(64 * 1024).compress(plaintext.as_slice(), &mut compressed).unwrap();
+        let mut transcoded = Vec::new();
+        transcode_block_size(compressed.as_slice(), &mut transcoded, 1024 * 1024, CompressionSettings::default().linked_blocks()).unwrap();
+        let frame_reader = LZ4FrameReader::new(transcoded.as_slice()).unwrap();
+        assert_eq!((frame_reader.block_size(), frame_reader.frame_size()), (Some(1024 * 1024), None));
+        assert!(decompress_frame(transcoded.as_slice()).unwrap() == plaintext);
+
+        assert!(matches!(transcode_block_size(compressed.as_slice(), io::sink(), 1000, &CompressionSettings::default()),
+            Err(TranscodeError::Compression(CompressionError::InvalidBlockSize))));
+    }
+}
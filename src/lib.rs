@@ -12,14 +12,18 @@ pub mod framed;
 
 pub use framed::{LZ4FrameReader, CompressionSettings};
 
+#[cfg(test)]
+mod test_data;
+
 
 
 
 #[cfg(test)]
 mod tests {
     use std::str;
-    use crate::raw::compress2;
+    use crate::raw::{compress2, compress_bound, compress_fill, compress_into, OutputTooSmall, TableHash, U16HashTable, U32HashTable};
     use crate::raw::test::decompress;
+    use crate::test_data::{noise, Noise};
 
     fn compress(input: &[u8]) -> Vec<u8> {
         let mut buf = Vec::new();
@@ -104,4 +108,90 @@ mod tests {
 
         assert_eq!(&decompress(&compress(&s)).unwrap(), &s);
     }
+
+    #[test]
+    fn within_bound() {
+        let mut noise = Noise::default();
+        // all-unique (as far as the compressor is concerned) and all-repeated data of various lengths
+        let mut inputs: Vec<Vec<u8>> = [0, 1, 12, 13, 255, 1000, 70_000, 300_000].iter().map(|&len| noise.bytes(len)).collect();
+        inputs.extend([0, 1, 13, 1000, 300_000].iter().map(|&len| vec![b'a'; len]));
+        inputs.push((0..=255u8).cycle().take(100_000).collect());
+        for input in &inputs {
+            let compressed = compress(input);
+            assert!(compressed.len() <= compress_bound(input.len()), "{} bytes for {}", compressed.len(), input.len());
+            assert_eq!(&decompress(&compressed).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn into_slice() {
+        let text = "The Read trait allows for reading bytes from a source.".repeat(2000);
+        for input in &[text.as_bytes(), &text.as_bytes()[..1000]] {
+            let mut output = vec![0; compress_bound(input.len())];
+            let len = compress_into(input, &mut output).unwrap();
+            assert_eq!(&decompress(&output[..len]).unwrap(), input);
+            assert_eq!(compress_into(input, &mut output[..len]), Ok(len));
+            assert_eq!(compress_into(input, &mut output[..len - 1]), Err(OutputTooSmall));
+        }
+    }
+
+    #[test]
+    fn fill() {
+        let noise = noise(20_000);
+        let text = "The Read trait allows for reading bytes from a source.".repeat(2000);
+        let zeros = vec![0; 300_000];
+        for input in &[&noise[..], text.as_bytes(), &zeros, &text.as_bytes()[..30]] {
+            for &size in &[0, 1, 2, 10, 16, 17, 100, 300, 4096, 70_000] {
+                let mut output = vec![0; size];
+                let (consumed, written) = compress_fill(input, &mut output);
+                assert!(consumed <= input.len() && written <= size);
+                if size >= compress_bound(input.len()) {
+                    assert_eq!(consumed, input.len());
+                }
+                if size > 1 {
+                    assert!(consumed > 0);
+                }
+                if written > 0 {
+                    assert_eq!(decompress(&output[..written]).unwrap(), &input[..consumed], "{} into {}", input.len(), size);
+                }
+            }
+        }
+    }
+
+    /// The worst possible hash: every position ends up in the same slot.
+    struct ConstantHash;
+    impl TableHash for ConstantHash {
+        fn hash_for_u32(_: &[u8]) -> usize { 0 }
+        fn hash_for_u16(_: &[u8]) -> usize { 0 }
+    }
+
+    /// A different hash, which also returns values that are way too large for the table.
+    struct FoldingHash;
+    impl TableHash for FoldingHash {
+        fn hash_for_u32(input: &[u8]) -> usize {
+            input.iter().take(5).fold(0, |h: usize, &b| h.wrapping_mul(2_246_822_519) ^ b as usize)
+        }
+        fn hash_for_u16(input: &[u8]) -> usize { Self::hash_for_u32(&input[..4]) }
+    }
+
+    fn inverse_with_hash<H: TableHash>(input: &[u8]) {
+        let mut compressed = Vec::new();
+        compress2(input, 0, &mut U32HashTable::<H>::default(), &mut compressed).unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), input);
+        if input.len() <= 0xFFFF {
+            compressed.clear();
+            compress2(input, 0, &mut U16HashTable::<H>::default(), &mut compressed).unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn custom_hash() {
+        let text = "The Read trait allows for reading bytes from a source. Implementors of the Read trait are called 'readers'.".repeat(100);
+        let pattern: Vec<u8> = (0..200_000u32).map(|n| (n as u8).wrapping_mul(0xA) ^ (n >> 10) as u8).collect();
+        for input in &[text.as_bytes(), &pattern, b"short"] {
+            inverse_with_hash::<ConstantHash>(input);
+            inverse_with_hash::<FoldingHash>(input);
+        }
+    }
 }
@@ -1,12 +1,19 @@
 use std::mem;
+use std::marker::PhantomData;
 use std::cmp;
-use std::io::Write;
+use std::io::{self, Write, ErrorKind};
 use std::convert::{TryInto, TryFrom};
 use byteorder::{ByteOrder, NativeEndian, WriteBytesExt, LE};
+use thiserror::Error;
 use fehler::{throws};
 
 type Error = std::io::Error;
 
+/// The error of `compress_into`: the compressed block doesn't fit into the output buffer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Error)]
+#[error("the compressed block doesn't fit into the output buffer")]
+pub struct OutputTooSmall;
+
 /// Duplication dictionary size.
 ///
 /// Every four bytes is assigned an entry. When this number is lower, fewer entries exists, and
@@ -24,14 +31,36 @@ pub trait EncoderTable {
     fn offset(&mut self, offset: usize);
 }
 
+/// The hash function that assigns input positions to the slots of an encoder table.
+///
+/// Implement this to experiment with different hash functions, the default is `DefaultHash`.
+/// Only the lowest bits of the result are used (the tables have 4096 and 8192 slots respectively).
+/// The choice of hash function only affects the compression ratio and speed, the output is always valid.
+pub trait TableHash {
+    /// Hash the start of `input` for `U32Table`. Near the end of the input, `input` can be shorter than eight bytes.
+    fn hash_for_u32(input: &[u8]) -> usize { hash_for_u32(input) }
+    /// Hash the start of `input` for `U16Table`.
+    fn hash_for_u16(input: &[u8]) -> usize { hash_for_u16(input) }
+}
+
+/// The hash function we use by default, borrowed from the reference implementation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultHash;
+impl TableHash for DefaultHash {}
+
+/// The encoder table for inputs up to 4 GiB, using the default hash function.
+pub type U32Table = U32HashTable<DefaultHash>;
+
+/// `U32Table` with a custom hash function.
 #[derive(Clone)]
-pub struct U32Table {
+pub struct U32HashTable<H: TableHash> {
     dict: [u32; DICTIONARY_SIZE],
     offset: usize,
+    hash: PhantomData<fn() -> H>,
 }
-impl Default for U32Table {
+impl<H: TableHash> Default for U32HashTable<H> {
     fn default() -> Self {
-        U32Table { dict: [0; DICTIONARY_SIZE], offset: 0 }
+        U32HashTable { dict: [0; DICTIONARY_SIZE], offset: 0, hash: PhantomData }
     }
 }
 
@@ -60,12 +89,12 @@ fn hash_for_u16(input: &[u8]) -> usize {
     (v.wrapping_mul(2654435761) >> (32 - HASHLOG - 1)) as usize // shift by one less than hashlog because we have twice as many slots
 }
 
-impl EncoderTable for U32Table {
+impl<H: TableHash> EncoderTable for U32HashTable<H> {
     fn replace(&mut self, input: &[u8], offset: usize) -> usize {
         let o = offset + self.offset; // apply positive offset on input
 
         let mut value = o.try_into().expect("EncoderTable contract violated");
-        mem::swap(&mut self.dict[hash_for_u32(&input[offset..])], &mut value);
+        mem::swap(&mut self.dict[H::hash_for_u32(&input[offset..]) % DICTIONARY_SIZE], &mut value);
         usize::try_from(value).expect("This code is not supposed to run on a 16-bit arch (let alone smaller)")
             .saturating_sub(self.offset) // apply negative offset on output
     }
@@ -75,22 +104,27 @@ impl EncoderTable for U32Table {
     fn payload_size_limit() -> usize { std::u32::MAX as usize }
 }
 
+/// The encoder table for inputs up to 64 KiB, using the default hash function.
+pub type U16Table = U16HashTable<DefaultHash>;
+
+/// `U16Table` with a custom hash function.
 #[derive(Clone)]
-pub struct U16Table {
+pub struct U16HashTable<H: TableHash> {
     dict: [u16; DICTIONARY_SIZE*2], // u16 fits twice as many slots into the same amount of memory
     offset: usize,
+    hash: PhantomData<fn() -> H>,
 }
-impl Default for U16Table {
+impl<H: TableHash> Default for U16HashTable<H> {
     fn default() -> Self {
-        U16Table { dict: [0; DICTIONARY_SIZE*2], offset: 0 }
+        U16HashTable { dict: [0; DICTIONARY_SIZE*2], offset: 0, hash: PhantomData }
     }
 }
-impl EncoderTable for U16Table {
+impl<H: TableHash> EncoderTable for U16HashTable<H> {
     fn replace(&mut self, input: &[u8], offset: usize) -> usize {
         let o = offset + self.offset; // apply positive offset on input
 
         let mut value = o.try_into().expect("EncoderTable contract violated");
-        mem::swap(&mut self.dict[hash_for_u16(&input[offset..])], &mut value);
+        mem::swap(&mut self.dict[H::hash_for_u16(&input[offset..]) % (DICTIONARY_SIZE*2)], &mut value);
         usize::try_from(value).expect("This code is not supposed to run on a 16-bit arch (let alone smaller)")
             .saturating_sub(self.offset) // apply negative offset on output
     }
@@ -162,12 +196,223 @@ fn write_group<W: Write>(mut writer: &mut W, literal: &[u8], duplicate: Duplicat
         write_lsic_tail(&mut writer, duplicate.extra_bytes)?;
 }
 
+/// The largest size that compressing `input_len` bytes into a raw LZ4 block can result in.
+///
+/// Incompressible data grows a little due to the tokens and length fields, this is the same bound
+/// as `LZ4_compressBound` in the reference implementation.
+pub fn compress_bound(input_len: usize) -> usize {
+    input_len + input_len / 255 + 16
+}
+
+/// Compress `input[window_offset..]` into a single raw LZ4 block and write it to `writer`.
+///
+/// Everything before `window_offset` is the window: data that is not compressed itself but may be referenced
+/// by the block, such as a dictionary or the end of the previous block. The decompressor needs exactly the same
+/// data as its prefix. Keep in mind that LZ4 can only reference up to 64 KiB back.
+///
+/// `table` must not contain positions from any other `input`. Matches in the window are only found
+/// if the window is indexed in `table`, either because the table was used to compress the window
+/// or by calling `EncoderTable::replace` for positions in the window yourself.
+/// For `U16Table`, `input` (including the window) must not exceed 64 KiB.
+///
+/// The only errors are the ones returned by `writer`. The block is at most `compress_bound(input.len() - window_offset)` bytes.
+///
+/// ```
+/// use lz_fear::raw::{compress2, decompress_raw, EncoderTable, U32Table};
+///
+/// let text = b"Let's compress this text, this text, this very text!";
+/// let mut compressed = Vec::new();
+/// compress2(text, 0, &mut U32Table::default(), &mut compressed).unwrap();
+///
+/// let mut output = Vec::new();
+/// decompress_raw(&compressed, &[], &mut output, text.len()).unwrap();
+/// assert_eq!(output, &text[..]);
+///
+/// // now with some window: only the part after the window is compressed, but it may reference the window
+/// let window_offset = 25;
+/// let mut table = U32Table::default();
+/// for position in 0..window_offset {
+///     table.replace(text, position);
+/// }
+/// let mut compressed = Vec::new();
+/// compress2(text, window_offset, &mut table, &mut compressed).unwrap();
+/// assert!(compressed.len() < text.len() - window_offset);
+///
+/// let mut output = Vec::new();
+/// decompress_raw(&compressed, &text[..window_offset], &mut output, text.len()).unwrap();
+/// assert_eq!(output, &text[window_offset..]);
+/// ```
+#[throws]
+pub fn compress2<W: Write, T: EncoderTable>(input: &[u8], window_offset: usize, table: &mut T, writer: W) {
+    compress_window(input, window_offset, true, table, writer)?;
+}
+
+/// Like `compress2`, but treats the window like the reference implementation treats a separate buffer
+/// (the dictionary or the saved end of the previous block): a match that starts in the block is never
+/// extended backwards into the window. The framed compressor uses this to produce identical output.
 #[throws]
-pub fn compress2<W: Write, T: EncoderTable>(input: &[u8], cursor: usize, table: &mut T, mut writer: W) {
+pub(crate) fn compress2_separate_window<W: Write, T: EncoderTable>(input: &[u8], window_offset: usize, table: &mut T, writer: W) {
+    compress_window(input, window_offset, false, table, writer)?;
+}
+
+/// Compress `input` into a single raw LZ4 block in `output`, returning the length of the block.
+///
+/// If `output` is at least `compress_bound(input.len())` bytes, this always succeeds.
+/// A smaller buffer is fine too, as long as the block happens to fit.
+///
+/// ```
+/// use lz_fear::raw::{compress_into, compress_bound, decompress_raw};
+///
+/// let text = b"Let's compress this text, this text, this very text!";
+/// let mut compressed = vec![0; compress_bound(text.len())];
+/// let len = compress_into(text, &mut compressed).unwrap();
+///
+/// let mut output = Vec::new();
+/// decompress_raw(&compressed[..len], &[], &mut output, text.len()).unwrap();
+/// assert_eq!(output, &text[..]);
+/// assert!(compress_into(text, &mut compressed[..10]).is_err());
+/// ```
+pub fn compress_into(input: &[u8], output: &mut [u8]) -> Result<usize, OutputTooSmall> {
+    if input.len() <= U16Table::payload_size_limit() {
+        compress_separate_window_into(input, 0, &mut U16Table::default(), output)
+    } else {
+        compress_separate_window_into(input, 0, &mut U32Table::default(), output)
+    }
+}
+
+/// Compress as much of `input` as fits into `output`, like `LZ4_compress_destSize` in the reference implementation.
+///
+/// Returns how many bytes of `input` were consumed and how many bytes of `output` were written:
+/// `output[..written]` is a raw LZ4 block that decompresses to exactly `input[..consumed]`.
+/// If not even a single byte fits, this returns `(0, 0)`.
+///
+/// ```
+/// use lz_fear::raw::{compress_fill, decompress_raw};
+///
+/// let text = b"Let's compress this text, this text, this very text!".repeat(100);
+/// let mut page = [0; 64];
+/// let (consumed, written) = compress_fill(&text, &mut page);
+///
+/// let mut output = Vec::new();
+/// decompress_raw(&page[..written], &[], &mut output, consumed).unwrap();
+/// assert_eq!(output, &text[..consumed]);
+/// ```
+pub fn compress_fill(input: &[u8], output: &mut [u8]) -> (usize, usize) {
+    // every output byte encodes at most 255 input bytes, so there is no point in compressing more than this
+    let input = &input[..cmp::min(input.len(), output.len().saturating_mul(255).saturating_add(12))];
+    let mut block = Vec::new();
+    if input.len() <= U16Table::payload_size_limit() {
+        compress_window(input, 0, false, &mut U16Table::default(), &mut block)
+    } else {
+        compress_window(input, 0, false, &mut U32Table::default(), &mut block)
+    }.expect("writing to a Vec can't fail");
+
+    if block.len() <= output.len() {
+        output[..block.len()].copy_from_slice(&block);
+        return (input.len(), block.len());
+    }
+
+    // Cut the block after one of its sequences and encode the rest as literals.
+    // We walk the block, each sequence boundary is a place where we could do that.
+    let capacity = output.len();
+    let mut best: Option<(usize, usize, usize)> = None; // (consumed, block bytes kept, final literals)
+    let mut consider = |kept: usize, decoded: usize, last_match_start: Option<usize>| {
+        if let Some(literals) = final_literals(capacity, kept, input.len() - decoded) {
+            let consumed = decoded + literals;
+            // the same rules the compressor follows at the end of the input: the last five bytes
+            // are literals and the last match starts at least twelve bytes before the end
+            let valid = match last_match_start {
+                Some(start) => literals >= 5 && consumed >= start + 12,
+                None => true,
+            };
+            if valid && consumed > best.map_or(0, |(c, _, _)| c) {
+                best = Some((consumed, kept, literals));
+            }
+        }
+    };
+    consider(0, 0, None);
+    let mut pos = 0;
+    let mut decoded = 0;
+    loop {
+        let token = block[pos];
+        pos += 1;
+        let literals = read_lsic(&block, &mut pos, token >> 4);
+        pos += literals;
+        decoded += literals;
+        if pos == block.len() {
+            break; // the final literal-only sequence
+        }
+        pos += 2; // offset
+        let match_len = read_lsic(&block, &mut pos, token & 0xF) + MINMATCH;
+        let match_start = decoded;
+        decoded += match_len;
+        consider(pos, decoded, Some(match_start));
+    }
+
+    match best {
+        Some((consumed, kept, literals)) => {
+            output[..kept].copy_from_slice(&block[..kept]);
+            let mut writer = NoPartialWrites(&mut output[kept..]);
+            let mut token = 0;
+            write_lsic_head(&mut token, 4, literals);
+            writer.write_u8(token).and_then(|()| write_lsic_tail(&mut writer, literals))
+                .and_then(|()| writer.write_all(&input[consumed - literals..consumed]))
+                .expect("we made sure the literals fit");
+            (consumed, capacity - writer.0.len())
+        }
+        None => (0, 0),
+    }
+}
+
+/// How many literals (up to `available`) fit into a final sequence after `used` bytes of an output of `capacity` bytes.
+fn final_literals(capacity: usize, used: usize, available: usize) -> Option<usize> {
+    fn tail_len(literals: usize) -> usize {
+        if literals < 0xF { 0 } else { (literals - 0xF) / 0xFF + 1 }
+    }
+    let space = capacity.checked_sub(used)?.checked_sub(1)?; // minus the token
+    let mut literals = cmp::min(available, space - tail_len(space));
+    while literals < available && literals + 1 + tail_len(literals + 1) <= space {
+        literals += 1;
+    }
+    Some(literals)
+}
+
+/// Read the rest of a length field whose token nibble is `head`.
+fn read_lsic(block: &[u8], pos: &mut usize, head: u8) -> usize {
+    let mut value = head as usize;
+    if head == 0xF {
+        loop {
+            let byte = block[*pos];
+            *pos += 1;
+            value += byte as usize;
+            if byte != 0xFF {
+                break;
+            }
+        }
+    }
+    value
+}
+
+/// Like `compress2_separate_window`, but into a slice like `compress_into`.
+pub(crate) fn compress_separate_window_into<T: EncoderTable>(input: &[u8], window_offset: usize, table: &mut T, output: &mut [u8]) -> Result<usize, OutputTooSmall> {
+    let capacity = output.len();
+    let mut writer = NoPartialWrites(output);
+    match compress_window(input, window_offset, false, table, &mut writer) {
+        Ok(()) => Ok(capacity - writer.0.len()),
+        Err(e) => {
+            // this is the only error NoPartialWrites can return
+            debug_assert_eq!(e.kind(), ErrorKind::WriteZero);
+            Err(OutputTooSmall)
+        }
+    }
+}
+
+#[throws]
+fn compress_window<W: Write, T: EncoderTable>(input: &[u8], window_offset: usize, backtrack_into_window: bool, table: &mut T, mut writer: W) {
     assert!(input.len() <= T::payload_size_limit());
 
-    let init_cursor = cursor;
-    let mut cursor = cursor;
+    let init_cursor = window_offset;
+    let mut cursor = window_offset;
     while cursor < input.len() {
         let literal_start = cursor;
 
@@ -208,14 +453,21 @@ pub fn compress2<W: Write, T: EncoderTable>(input: &[u8], cursor: usize, table:
                     let offset = (cursor - candidate) as u16;
 
                     // backtrack
-                    let max_backtrack = cursor - literal_start;
+                    let max_backtrack = if backtrack_into_window || candidate < window_offset {
+                        cursor - literal_start
+                    } else {
+                        cmp::min(cursor - literal_start, candidate - window_offset)
+                    };
                     let backtrack = input[..cursor].iter().rev().zip(input[..candidate].iter().rev()).take(max_backtrack).take_while(|&(a, b)| a == b).count();
                     // offset remains unchanged
                     extra_bytes += backtrack;
                     cursor += matching_bytes;
 
                     // not sure why exactly cursor - 2, but that's what they do
-                    table.replace(input, cursor - 2);
+                    // (and only if there is still room for another match - this matters for linked blocks)
+                    if input.len() - cursor >= 12 {
+                        table.replace(input, cursor - 2);
+                    }
         
                     break Duplicate { offset, extra_bytes };
                 }
@@ -259,3 +511,32 @@ fn write_lsic_tail<W: Write>(writer: &mut W, mut value: usize) {
     writer.write_u8(value as u8)?;
 }
 
+/// Helper struct to allow more efficient code generation when using the Write trait on byte buffers.
+///
+/// The underlying problem is that the Write impl on [u8] (and everything similar, e.g. Cursor<[u8]>)
+/// is specified to write as many bytes as possible before returning an error.
+/// This is a problem because it forces e.g. a 32-bit write to compile to four 8-bit writes with a range
+/// check every time, rather than a single 32-bit write with a range check.
+///
+/// This wrapper aims to resolve the problem by simply not writing anything in case we fail the bounds check,
+/// as we throw away the entire buffer in that case anyway.
+struct NoPartialWrites<'a>(&'a mut [u8]);
+impl<'a> Write for NoPartialWrites<'a> {
+    #[inline]
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.0.len() < data.len() {
+            return Err(ErrorKind::WriteZero.into());
+        }
+
+        let amt = data.len();
+        let (a, b) = mem::take(&mut self.0).split_at_mut(data.len());
+        a.copy_from_slice(data);
+        self.0 = b;
+        Ok(amt)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
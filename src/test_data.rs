@@ -0,0 +1,50 @@
+//! Test data shared by the unit tests and (through `#[path]`) the integration tests.
+// not every test binary uses everything
+#![allow(dead_code)]
+
+/// `len` bytes that compress well, but not trivially: a 251 byte cycle that changes every 1000 bytes.
+pub fn compressible(len: u32) -> Vec<u8> {
+    compressible_changing_every(len, 1000)
+}
+
+/// Like `compressible`, but the cycle changes every `run` bytes.
+pub fn compressible_changing_every(len: u32, run: u32) -> Vec<u8> {
+    (0..len).map(|i| compressible_byte(i, run)).collect()
+}
+
+/// Byte `i` of `compressible_changing_every(_, run)`, for mixing it with `Noise`.
+pub fn compressible_byte(i: u32, run: u32) -> u8 {
+    (i % 251) as u8 ^ (i / run) as u8
+}
+
+/// Deterministic incompressible bytes (xorshift32).
+pub struct Noise(u32);
+
+impl Noise {
+    pub fn with_seed(seed: u32) -> Self {
+        Noise(seed)
+    }
+
+    pub fn byte(&mut self) -> u8 {
+        let state = &mut self.0;
+        *state ^= *state << 13;
+        *state ^= *state >> 17;
+        *state ^= *state << 5;
+        *state as u8
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.byte()).collect()
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise::with_seed(0x1234_5678)
+    }
+}
+
+/// The first `len` bytes of `Noise::default()`.
+pub fn noise(len: usize) -> Vec<u8> {
+    Noise::default().bytes(len)
+}
@@ -6,7 +6,7 @@ fn main() {
 
     let mut compressed = vec![];
     lz_fear::framed::CompressionSettings::default()
-        .independent_blocks(false)
+        .linked_blocks()
         .block_size(64 * 1024)
         .compress(&input[..], &mut compressed)
         .unwrap();
@@ -0,0 +1,78 @@
+//! Linked blocks that are much smaller than the 64 KiB window, as produced by flushing a `LZ4FrameWriter`.
+use lz_fear::framed::{decompress_frame, CompressionSettings};
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+#[path = "../src/test_data.rs"]
+mod test_data;
+use test_data::Noise;
+
+/// Text with repetitions at all kinds of distances, interleaved with incompressible noise
+/// (which ends up in stored blocks that later blocks reference).
+fn plaintext() -> Vec<u8> {
+    let mut noise = Noise::default();
+    let mut data = Vec::new();
+    for round in 0..6 {
+        let random = noise.bytes(20 * 1024);
+        data.extend_from_slice(&random);
+        data.extend_from_slice(format!("round {} of some text that repeats itself. ", round).repeat(100).as_bytes());
+        // far enough back that it spans many small blocks, but still within the window
+        let len = data.len();
+        data.extend_from_within(len.saturating_sub(50 * 1024)..len - 10 * 1024);
+        data.extend_from_slice(&random[..5000]);
+    }
+    data
+}
+
+fn compress_flushing(plaintext: &[u8], flush_every: usize) -> Vec<u8> {
+    let mut writer = CompressionSettings::default()
+        .linked_blocks()
+        .block_size(64 * 1024)
+        .streaming_compress(Vec::new())
+        .unwrap();
+    for chunk in plaintext.chunks(flush_every) {
+        writer.write_all(chunk).unwrap();
+        writer.flush().unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+static BLOCK_SIZES: &[usize] = &[1024, 3000, 4096, 16 * 1024, 40_000, 64 * 1024];
+
+#[test]
+fn roundtrip_small_linked_blocks() {
+    let plaintext = plaintext();
+    for &block_size in BLOCK_SIZES {
+        let compressed = compress_flushing(&plaintext, block_size);
+        assert!(decompress_frame(compressed.as_slice()).unwrap() == plaintext, "block size {}", block_size);
+    }
+}
+
+/// Needs the reference `lz4` binary on the `PATH`, and is skipped (with a message) without it.
+#[test]
+fn reference_decodes_small_linked_blocks() {
+    let plaintext = plaintext();
+    for &block_size in BLOCK_SIZES {
+        let compressed = compress_flushing(&plaintext, block_size);
+
+        let lz4 = Command::new("lz4")
+            .args(["-d", "-c"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut lz4 = match lz4 {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                eprintln!("skipping reference_decodes_small_linked_blocks: the lz4 binary is not on the PATH");
+                return;
+            }
+            lz4 => lz4.unwrap(),
+        };
+        let mut stdin = lz4.stdin.take().unwrap();
+        let feeder = std::thread::spawn(move || stdin.write_all(&compressed).unwrap());
+        let output = lz4.wait_with_output().unwrap();
+        feeder.join().unwrap();
+
+        assert!(output.status.success(), "block size {}", block_size);
+        assert!(output.stdout == plaintext, "block size {}", block_size);
+    }
+}
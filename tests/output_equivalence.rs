@@ -33,7 +33,7 @@ fn test() {
     
     CompressionSettings::default()
         .content_checksum(true)
-        .independent_blocks(true)
+        .unlinked_blocks()
         /*.block_size(64 * 1024).dictionary(0, &vec![0u8; 64 * 1024]).dictionary_id_nonsense_override(Some(42))*/
         .compress_with_size(file_in, file_out)?;
 }
@@ -65,7 +65,7 @@ fn run_test() {
         }
 
         if bits & 2 != 0 {
-            settings.independent_blocks(false);
+            settings.linked_blocks();
             args.push("-BD");
         }
 
@@ -0,0 +1,61 @@
+//! Counts allocations, so this needs its own test binary with nothing else running.
+use lz_fear::framed::{CompressionSettings, LZ4FrameReader};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn reset_reuses_buffers() {
+    let frames: Vec<Vec<u8>> = (0..1000u32).map(|i| {
+        let plaintext = format!("frame number {} says hello, frame number {} says hello", i, i).repeat(3);
+        let mut compressed = Vec::new();
+        let mut settings = CompressionSettings::default();
+        if i % 2 != 0 {
+            settings.linked_blocks();
+        }
+        settings.compress(plaintext.as_bytes(), &mut compressed).unwrap();
+        compressed
+    }).collect();
+
+    let mut reader = LZ4FrameReader::new(&frames[0][..]).unwrap();
+    let mut output = Vec::with_capacity(1024);
+    let mut decode_all = |reader: &mut LZ4FrameReader<&[u8]>| loop {
+        output.clear();
+        reader.decode_block(&mut output, &[]).unwrap();
+        if output.is_empty() {
+            break;
+        }
+    };
+
+    // warm up the buffers with one frame of each kind
+    decode_all(&mut reader);
+    reader.reset(&frames[1][..]).unwrap();
+    decode_all(&mut reader);
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for frame in &frames {
+        reader.reset(&frame[..]).unwrap();
+        decode_all(&mut reader);
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    assert!(allocations < 10, "decoding 1000 frames took {} allocations", allocations);
+}
@@ -0,0 +1,176 @@
+//! Frames produced by the reference `lz4` command line tool (v1.9.4), see `tests/fixtures`.
+use lz_fear::framed::{decompress_frame, decompress_frame_slice, CompressionSettings};
+use std::io::Cursor;
+
+#[path = "../src/test_data.rs"]
+mod test_data;
+use test_data::noise;
+
+static PLAINTEXT: &[u8] = include_bytes!("fixtures/plaintext.txt");
+static DICTIONARY: &[u8] = include_bytes!("fixtures/dictionary.bin");
+
+struct Fixture {
+    /// How the fixture was created: `lz4 <flags> plaintext.txt <name>.lz4`
+    name: &'static str,
+    compressed: &'static [u8],
+    /// Whether our compressor is expected to reproduce the fixture byte for byte.
+    identical: bool,
+    /// Applied on top of `base_settings()`.
+    settings: fn(&mut CompressionSettings<'static>),
+    with_size: bool,
+}
+
+static FIXTURES: &[Fixture] = &[
+    Fixture {
+        // (no flags)
+        name: "default",
+        compressed: include_bytes!("fixtures/default.lz4"),
+        identical: true,
+        settings: |_| (),
+        with_size: false,
+    },
+    Fixture {
+        // --no-frame-crc
+        name: "no_content_checksum",
+        compressed: include_bytes!("fixtures/no_content_checksum.lz4"),
+        identical: true,
+        settings: |s| { s.content_checksum(false); },
+        with_size: false,
+    },
+    Fixture {
+        // -BX
+        name: "block_checksums",
+        compressed: include_bytes!("fixtures/block_checksums.lz4"),
+        identical: true,
+        settings: |s| { s.block_checksums(true); },
+        with_size: false,
+    },
+    Fixture {
+        // --content-size
+        name: "content_size",
+        compressed: include_bytes!("fixtures/content_size.lz4"),
+        identical: true,
+        settings: |_| (),
+        with_size: true,
+    },
+    Fixture {
+        // -D dictionary.bin
+        name: "dictionary",
+        compressed: include_bytes!("fixtures/dictionary.lz4"),
+        identical: true,
+        settings: |s| { s.dictionary(0, DICTIONARY).dictionary_id_nonsense_override(None); },
+        with_size: false,
+    },
+    Fixture {
+        // -B4
+        name: "small_blocks",
+        compressed: include_bytes!("fixtures/small_blocks.lz4"),
+        identical: false,
+        settings: |s| { s.block_size(64 * 1024); },
+        with_size: false,
+    },
+    Fixture {
+        // -B4 -BD
+        name: "linked_blocks",
+        compressed: include_bytes!("fixtures/linked_blocks.lz4"),
+        identical: false,
+        settings: |s| { s.block_size(64 * 1024).linked_blocks(); },
+        with_size: false,
+    },
+    Fixture {
+        // -9 -B4 (the high compression mode finds longer and more distant matches than we do)
+        name: "hc_small_blocks",
+        compressed: include_bytes!("fixtures/hc_small_blocks.lz4"),
+        identical: false,
+        settings: |s| { s.block_size(64 * 1024); },
+        with_size: false,
+    },
+    Fixture {
+        // -9 -B4 -BD
+        name: "hc_linked_blocks",
+        compressed: include_bytes!("fixtures/hc_linked_blocks.lz4"),
+        identical: false,
+        settings: |s| { s.block_size(64 * 1024).linked_blocks(); },
+        with_size: false,
+    },
+];
+
+/// The CLI picks the smallest block size that fits the entire input, which is 256 KiB for our plaintext.
+fn base_settings() -> CompressionSettings<'static> {
+    let mut settings = CompressionSettings::default();
+    settings.block_size(256 * 1024);
+    settings
+}
+
+fn decompress(compressed: &[u8], dictionary: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let reader = lz_fear::LZ4FrameReader::new(compressed).unwrap();
+    std::io::Read::read_to_end(&mut reader.into_read_with_dictionary(dictionary), &mut output).unwrap();
+    output
+}
+
+fn dictionary_for(fixture: &Fixture) -> &'static [u8] {
+    if fixture.name == "dictionary" { DICTIONARY } else { &[] }
+}
+
+#[test]
+fn decompress_reference_output() {
+    for fixture in FIXTURES {
+        let output = decompress(fixture.compressed, dictionary_for(fixture));
+        assert!(output == PLAINTEXT, "fixture {} did not decompress correctly", fixture.name);
+    }
+}
+
+#[test]
+fn decompress_frame_reference_output() {
+    for fixture in FIXTURES.iter().filter(|f| f.name != "dictionary") {
+        assert!(decompress_frame(fixture.compressed).unwrap() == PLAINTEXT, "fixture {}", fixture.name);
+        assert!(decompress_frame_slice(fixture.compressed).unwrap() == PLAINTEXT, "fixture {}", fixture.name);
+    }
+}
+
+#[test]
+fn cli_compatible_preset() {
+    let mut output = Vec::new();
+    CompressionSettings::lz4_cli_compatible().compress(PLAINTEXT, &mut output).unwrap();
+    assert!(output == FIXTURES[0].compressed);
+
+    // what the CLI produces for empty input
+    let mut output = Vec::new();
+    CompressionSettings::lz4_cli_compatible().compress(&[][..], &mut output).unwrap();
+    assert_eq!(output, [0x04, 0x22, 0x4D, 0x18, 0x64, 0x40, 0xA7, 0, 0, 0, 0, 0x05, 0x5D, 0xCC, 0x02]);
+}
+
+#[test]
+fn compress_like_reference() {
+    for fixture in FIXTURES {
+        let mut settings = base_settings();
+        (fixture.settings)(&mut settings);
+
+        let mut output = Vec::new();
+        if fixture.with_size {
+            settings.compress_with_size(Cursor::new(PLAINTEXT), &mut output).unwrap();
+        } else {
+            settings.compress(PLAINTEXT, &mut output).unwrap();
+        }
+
+        if fixture.identical {
+            assert!(output == fixture.compressed, "fixture {} was not reproduced exactly", fixture.name);
+        }
+        assert!(decompress(&output, dictionary_for(fixture)) == PLAINTEXT, "fixture {} failed to roundtrip", fixture.name);
+    }
+}
+
+/// `lz4 -BD -B4` on 64 KiB of text, 64 KiB of noise (a stored block) and then 32 KiB that repeat part
+/// of the noise, so the last block references the stored one.
+#[test]
+fn linked_blocks_reference_stored_block() {
+    let noise = noise(64 * 1024);
+    let mut plaintext: Vec<u8> = (0..2000).flat_map(|i| format!("line {} of the compressible first block\n", i).into_bytes()).take(64 * 1024).collect();
+    plaintext.extend_from_slice(&noise);
+    plaintext.extend_from_slice(&noise[20000..52768]);
+
+    let compressed = include_bytes!("fixtures/linked_stored_block.lz4");
+    assert!(decompress(compressed, &[]) == plaintext);
+    assert!(decompress_frame(&compressed[..]).unwrap() == plaintext);
+}